@@ -0,0 +1,140 @@
+// Transport-neutral frames for the outbound channel
+// Payloads are serialized once when queued; cloning a frame for every client
+// in a broadcast is just a refcount bump, never a copy
+use crate::websocket::{BinaryMessage, Classify, CloseMessage, Delivery, TextMessage};
+use axum::body::Bytes;
+use axum::extract::ws::Utf8Bytes;
+
+/// An already-framed message waiting in a client's queue.
+///
+/// The socket writer converts these into whatever its transport speaks, so nothing
+/// upstream of the channel depends on axum's `Message`.
+///
+/// ```
+/// use appstate::OutboundFrame;
+/// use axum::extract::ws::Message;
+///
+/// let frame = OutboundFrame::Text("hello".into());
+/// assert_eq!(frame.len(), 5);
+/// assert_eq!(Message::from(frame), Message::Text("hello".into()));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutboundFrame {
+    // Utf8Bytes rather than a String so neither cloning nor handing it to axum copies it
+    Text(Utf8Bytes),
+    Binary(Bytes),
+    Ping(Bytes),
+    Pong(Bytes),
+    Close { code: u16, reason: Utf8Bytes },
+}
+
+impl OutboundFrame {
+    /// Payload size on the wire, as counted by the outbound byte totals.
+    pub fn len(&self) -> usize {
+        match self {
+            OutboundFrame::Text(text) => text.as_str().len(),
+            OutboundFrame::Binary(data) | OutboundFrame::Ping(data) | OutboundFrame::Pong(data) => {
+                data.len()
+            }
+            // Close payloads are the 2 byte code followed by the reason
+            OutboundFrame::Close { reason, .. } => 2 + reason.as_str().len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Canvas data, as opposed to the control frames that keep the socket alive
+    pub fn is_data(&self) -> bool {
+        matches!(self, OutboundFrame::Text(_) | OutboundFrame::Binary(_))
+    }
+
+    pub fn is_close(&self) -> bool {
+        matches!(self, OutboundFrame::Close { .. })
+    }
+}
+
+impl TextMessage for OutboundFrame {
+    fn create_text_message(text: String) -> Self {
+        OutboundFrame::Text(text.into())
+    }
+}
+
+impl BinaryMessage for OutboundFrame {
+    fn create_binary_message(data: Vec<u8>) -> Self {
+        OutboundFrame::Binary(data.into())
+    }
+}
+
+impl CloseMessage for OutboundFrame {
+    fn create_close_message(code: u16, reason: String) -> Self {
+        OutboundFrame::Close {
+            code,
+            reason: reason.into(),
+        }
+    }
+}
+
+// Same split as for axum messages - control chatter can be lost
+impl Classify for OutboundFrame {
+    fn delivery(&self) -> Delivery {
+        match self {
+            OutboundFrame::Ping(_) | OutboundFrame::Pong(_) => Delivery::Ephemeral,
+            OutboundFrame::Text(_) | OutboundFrame::Binary(_) | OutboundFrame::Close { .. } => {
+                Delivery::Critical
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ConnectionRegistry, MessageSender};
+    use axum::extract::ws::Message;
+
+    #[test]
+    fn text_frames_are_shared_not_copied() {
+        let frame = OutboundFrame::create_text_message("stroke data".repeat(100));
+        let OutboundFrame::Text(original) = &frame else {
+            unreachable!()
+        };
+        let payload = original.as_str().as_ptr();
+
+        // What a broadcast does for each client
+        let Message::Text(sent) = Message::from(frame.clone()) else {
+            panic!("text frame became something else")
+        };
+        assert_eq!(sent.as_str().as_ptr(), payload);
+        assert_eq!(frame.len(), sent.as_str().len());
+    }
+
+    #[test]
+    fn close_reasons_are_shared_not_copied() {
+        let frame = OutboundFrame::create_close_message(1001, "going away".to_string());
+        let OutboundFrame::Close { reason, .. } = &frame else {
+            unreachable!()
+        };
+        let payload = reason.as_str().as_ptr();
+        let Message::Close(Some(close)) = Message::from(frame.clone()) else {
+            panic!("close frame became something else")
+        };
+        assert_eq!(close.reason.as_str().as_ptr(), payload);
+    }
+
+    #[tokio::test]
+    async fn registry_broadcasts_arrive_as_frames() {
+        let registry = ConnectionRegistry::<OutboundFrame>::new();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+        registry.register(MessageSender::new(tx)).await;
+
+        registry.broadcast_text("hello".to_string()).await;
+        registry.broadcast_binary(vec![1, 2, 3]).await;
+        assert_eq!(rx.recv().await, Some(OutboundFrame::Text("hello".into())));
+        assert_eq!(
+            Message::from(rx.recv().await.unwrap()),
+            Message::Binary(vec![1, 2, 3].into())
+        );
+    }
+}
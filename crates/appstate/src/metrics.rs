@@ -0,0 +1,178 @@
+// Process-wide counters for /metrics - plain atomics, bumped from the socket tasks
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// What kind of frame a client sent us
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    Text,
+    Binary,
+    Ping,
+    Pong,
+    Close,
+}
+
+impl FrameKind {
+    const ALL: [FrameKind; 5] = [
+        FrameKind::Text,
+        FrameKind::Binary,
+        FrameKind::Ping,
+        FrameKind::Pong,
+        FrameKind::Close,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FrameKind::Text => "text",
+            FrameKind::Binary => "binary",
+            FrameKind::Ping => "ping",
+            FrameKind::Pong => "pong",
+            FrameKind::Close => "close",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    connections_opened: AtomicU64,
+    received: [AtomicU64; 5], // Indexed by FrameKind
+    bytes_received: AtomicU64,
+    messages_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+}
+
+impl Metrics {
+    pub fn connection_opened(&self) {
+        self.connections_opened.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn frame_received(&self, kind: FrameKind, bytes: usize) {
+        self.received[kind as usize].fetch_add(1, Ordering::Relaxed);
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn frame_sent(&self, bytes: usize) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Render everything in the Prometheus text exposition format.
+    ///
+    /// `active` and the room figures are passed in rather than counted here, so they always
+    /// match the registry instead of drifting from separate up/down counters. Only the rooms
+    /// given get their own series - pass a capped list (see
+    /// [`largest_rooms`](crate::ConnectionRegistry::largest_rooms)) to keep label cardinality
+    /// bounded; `total_rooms` still counts every room.
+    ///
+    /// ```
+    /// use appstate::{FrameKind, Metrics};
+    ///
+    /// let metrics = Metrics::default();
+    /// metrics.frame_received(FrameKind::Binary, 12);
+    /// let text = metrics.render(1, &[("canvas-1".to_string(), 1)], 4);
+    /// assert!(text.contains("rustcanvas_bytes_received_total 12\n"));
+    /// ```
+    pub fn render(&self, active: usize, rooms: &[(String, usize)], total_rooms: usize) -> String {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            let _ = writeln!(
+                out,
+                "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}"
+            );
+        };
+        metric(
+            "rustcanvas_connections_opened_total",
+            "counter",
+            "WebSocket connections accepted since start.",
+            load(&self.connections_opened),
+        );
+        metric(
+            "rustcanvas_connections_active",
+            "gauge",
+            "WebSocket connections currently open.",
+            active as u64,
+        );
+        metric(
+            "rustcanvas_bytes_received_total",
+            "counter",
+            "Payload bytes received from clients.",
+            load(&self.bytes_received),
+        );
+        metric(
+            "rustcanvas_frames_sent_total",
+            "counter",
+            "Frames written to clients.",
+            load(&self.messages_sent),
+        );
+        metric(
+            "rustcanvas_bytes_sent_total",
+            "counter",
+            "Payload bytes written to clients.",
+            load(&self.bytes_sent),
+        );
+
+        metric(
+            "rustcanvas_rooms",
+            "gauge",
+            "Rooms with at least one member.",
+            total_rooms as u64,
+        );
+
+        let name = "rustcanvas_room_members";
+        let _ = writeln!(out, "# HELP {name} Members of the busiest rooms.");
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        for (room, members) in rooms {
+            let _ = writeln!(out, "{name}{{room=\"{}\"}} {}", escape_label(room), members);
+        }
+
+        let name = "rustcanvas_frames_received_total";
+        let _ = writeln!(out, "# HELP {name} Frames received from clients, by kind.");
+        let _ = writeln!(out, "# TYPE {name} counter");
+        for kind in FrameKind::ALL {
+            let _ = writeln!(
+                out,
+                "{name}{{kind=\"{}\"}} {}",
+                kind.as_str(),
+                load(&self.received[kind as usize])
+            );
+        }
+        out
+    }
+}
+
+// Room names come from clients, so they can hold anything the exposition format cares about
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_reports_every_counter() {
+        let metrics = Metrics::default();
+        metrics.connection_opened();
+        metrics.frame_received(FrameKind::Binary, 12);
+        metrics.frame_sent(7);
+
+        let text = metrics.render(1, &[("canvas-1".to_string(), 1)], 4);
+        for line in [
+            "rustcanvas_connections_opened_total 1\n",
+            "rustcanvas_connections_active 1\n",
+            "rustcanvas_frames_received_total{kind=\"binary\"} 1\n",
+            "rustcanvas_frames_received_total{kind=\"text\"} 0\n",
+            "rustcanvas_bytes_received_total 12\n",
+            "rustcanvas_bytes_sent_total 7\n",
+            "rustcanvas_rooms 4\n",
+            "rustcanvas_room_members{room=\"canvas-1\"} 1\n",
+        ] {
+            assert!(text.contains(line), "missing {:?} in\n{}", line, text);
+        }
+    }
+}
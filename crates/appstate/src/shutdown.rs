@@ -0,0 +1,86 @@
+// Work that has to happen between "stop serving" and process exit
+// (flushing exporters, draining queues...). Registered by whoever owns the buffer
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use tokio::time::{Instant, timeout_at};
+
+type HookFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+type Hook = Box<dyn FnOnce() -> HookFuture + Send>;
+
+#[derive(Clone, Default)]
+pub struct ShutdownHooks {
+    hooks: Arc<StdMutex<Vec<(String, Hook)>>>,
+}
+
+impl ShutdownHooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Hooks run in the order they were added, one at a time
+    pub fn register<F, Fut>(&self, name: impl Into<String>, hook: F)
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let hook: Hook = Box::new(move || Box::pin(hook()));
+        self.hooks.lock().unwrap().push((name.into(), hook));
+    }
+
+    /// Run every registered hook, giving all of them together at most `limit`.
+    ///
+    /// Returns the names of the hooks that didn't finish in time, including any that never got
+    /// to start. Hooks are consumed, so a second call does nothing.
+    ///
+    /// ```
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// use appstate::ShutdownHooks;
+    /// use std::time::Duration;
+    ///
+    /// let hooks = ShutdownHooks::new();
+    /// hooks.register("flush metrics", || async { /* push the last batch */ });
+    /// let unfinished = hooks.run(Duration::from_secs(10)).await;
+    /// assert!(unfinished.is_empty());
+    /// # });
+    /// ```
+    pub async fn run(&self, limit: Duration) -> Vec<String> {
+        let hooks = std::mem::take(&mut *self.hooks.lock().unwrap());
+        let deadline = Instant::now() + limit;
+        let mut hooks = hooks.into_iter();
+
+        while let Some((name, hook)) = hooks.next() {
+            if timeout_at(deadline, hook()).await.is_err() {
+                // Out of time - this one and everything after it gets left behind
+                return std::iter::once(name)
+                    .chain(hooks.map(|(name, _)| name))
+                    .collect();
+            }
+        }
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[tokio::test]
+    async fn hooks_past_the_limit_are_reported_and_run_only_once() {
+        let hooks = ShutdownHooks::new();
+        let flushed = Arc::new(AtomicBool::new(false));
+        let flag = flushed.clone();
+        hooks.register("flush metrics", move || async move {
+            flag.store(true, Ordering::SeqCst)
+        });
+        hooks.register("stuck", std::future::pending);
+        hooks.register("after stuck", || async {});
+
+        let unfinished = hooks.run(Duration::from_millis(50)).await;
+        assert!(flushed.load(Ordering::SeqCst));
+        assert_eq!(unfinished, ["stuck", "after stuck"]);
+        assert!(hooks.run(Duration::from_millis(50)).await.is_empty());
+    }
+}
@@ -1,198 +1,1706 @@
-// Dependencies we need for the connection system
-// HashMap: track connections, Arc/Mutex: thread safety, mpsc: message channels
-use std::collections::HashMap;
-use std::fmt;
-use std::sync::Arc;
-use tokio::sync::{Mutex, RwLock, mpsc};
-
-// Simple ID type for clients - just a wrapper around a counter
-// Using a newtype pattern here to avoid mixing up with other u64s
-#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
-pub struct ConnectionId(pub u64);
-
-impl fmt::Display for ConnectionId {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.0)
-    }
-}
-
-// Message sender for talking to a specific client
-// Generic over message type so we can use different WS implementations
-#[derive(Clone)]
-pub struct MessageSender<T> {
-    tx: mpsc::Sender<T>,
-}
-
-impl<T> MessageSender<T>
-where
-    T: Clone + Send + 'static,
-{
-    pub fn new(tx: mpsc::Sender<T>) -> Self {
-        Self { tx }
-    }
-
-    // Basic send function - just passes through to the channel
-    // Returns error if the client disconnected
-    pub async fn send(&self, msg: T) -> Result<(), mpsc::error::SendError<T>> {
-        self.tx.send(msg).await
-    }
-}
-
-// Trait to abstract text message creation
-// Needed because different WS implementations have different message types
-pub trait TextMessage {
-    fn create_text_message(text: String) -> Self;
-}
-
-// Same idea but for binary data
-// This lets us avoid hardcoding to axum's message format
-pub trait BinaryMessage {
-    fn create_binary_message(data: Vec<u8>) -> Self;
-}
-
-// Add text sending capabilities if the message type supports it
-// This is conditional - only available if T implements TextMessage
-impl<T> MessageSender<T>
-where
-    T: TextMessage + Send + 'static,
-{
-    // Convenience wrapper for sending text - makes the API nicer
-    pub async fn send_text(
-        &self,
-        text: impl Into<String>,
-    ) -> Result<(), mpsc::error::SendError<T>> {
-        self.tx.send(T::create_text_message(text.into())).await
-    }
-}
-
-// Same pattern for binary - again, only available if T has binary capabilities
-impl<T> MessageSender<T>
-where
-    T: BinaryMessage + Send + 'static,
-{
-    // Send raw bytes to the client
-    pub async fn send_binary(
-        &self,
-        data: impl Into<Vec<u8>>,
-    ) -> Result<(), mpsc::error::SendError<T>> {
-        self.tx.send(T::create_binary_message(data.into())).await
-    }
-}
-
-// The core connection manager - tracks all active clients
-// Using RwLock for better concurrency (many reads, few writes)
-#[derive(Clone)]
-pub struct ConnectionRegistry<T> {
-    connections: Arc<RwLock<HashMap<ConnectionId, MessageSender<T>>>>,
-    next_id: Arc<Mutex<u64>>, // Counter for generating unique IDs
-}
-
-impl<T> ConnectionRegistry<T>
-where
-    T: Clone + Send + 'static,
-{
-    // Create fresh registry - start with empty map
-    // Starting IDs at 1 because 0 feels like a sentinel value
-    pub fn new() -> Self {
-        Self {
-            connections: Arc::new(RwLock::new(HashMap::new())),
-            next_id: Arc::new(Mutex::new(1)), // Start IDs from 1
-        }
-    }
-
-    // Add a new connection to the system
-    // Returns its unique ID that can be used to message it later
-    pub async fn register(&self, sender: MessageSender<T>) -> ConnectionId {
-        let mut id_guard = self.next_id.lock().await;
-        let id = ConnectionId(*id_guard);
-        *id_guard += 1; // Increment for next time
-
-        let mut connections = self.connections.write().await;
-        connections.insert(id, sender);
-        id
-    }
-
-    // Clean up when a client disconnects
-    // Returns true if we actually removed something
-    pub async fn unregister(&self, id: ConnectionId) -> bool {
-        let mut connections = self.connections.write().await;
-        connections.remove(&id).is_some()
-    }
-
-    // Look up a client by ID
-    // Returns None if it doesn't exist/disconnected
-    pub async fn get(&self, id: ConnectionId) -> Option<MessageSender<T>> {
-        let connections = self.connections.read().await;
-        connections.get(&id).cloned()
-    }
-
-    // Send the same message to all connected clients
-    // Failures are ignored - common pattern for broadcast
-    pub async fn broadcast(&self, msg: T) {
-        let connections = self.connections.read().await;
-        for sender in connections.values() {
-            // Don't care about errors here - it's fine if some clients miss a broadcast
-            let _ = sender.send(msg.clone()).await;
-        }
-    }
-
-    // How many clients are currently connected?
-    // Useful for debugging and stats
-    pub async fn count(&self) -> usize {
-        let connections = self.connections.read().await;
-        connections.len()
-    }
-
-    // Get IDs of all connected clients
-    // Useful for iterating through connections when needed
-    pub async fn all_ids(&self) -> Vec<ConnectionId> {
-        let connections = self.connections.read().await;
-        connections.keys().copied().collect()
-    }
-}
-
-// Add text broadcasting if message type supports it
-// Same conditional pattern as with MessageSender
-impl<T> ConnectionRegistry<T>
-where
-    T: TextMessage + Clone + Send + 'static,
-{
-    // Simpler API for broadcasting text
-    // This is used a lot, so worth having a dedicated method
-    pub async fn broadcast_text(&self, text: impl Into<String> + Clone) {
-        let text = text.into();
-        let connections = self.connections.read().await;
-        for sender in connections.values() {
-            // Again, don't care about errors in broadcast scenarios
-            let _ = sender.send_text(text.clone()).await;
-        }
-    }
-}
-
-// And the same for binary broadcasts
-// Not used as often but good to have for completeness
-impl<T> ConnectionRegistry<T>
-where
-    T: BinaryMessage + Clone + Send + 'static,
-{
-    // Send raw bytes to all clients
-    pub async fn broadcast_binary(&self, data: impl Into<Vec<u8>> + Clone) {
-        let data = data.into();
-        let connections = self.connections.read().await;
-        for sender in connections.values() {
-            // Ignore send errors as usual for broadcasts
-            let _ = sender.send_binary(data.clone()).await;
-        }
-    }
-}
-
-// Implement Default so we can use this with struct field defaults
-// Just delegates to new() to avoid duplicating logic
-impl<T> Default for ConnectionRegistry<T>
-where
-    T: Clone + Send + 'static,
-{
-    fn default() -> Self {
-        Self::new()
-    }
-}
+// Dependencies we need for the connection system
+// HashMap: track connections, Arc/Mutex: thread safety, mpsc: message channels
+use std::any::Any;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, RwLock, mpsc};
+use tokio::task::JoinSet;
+use utils::random::RandomSource;
+
+// Simple ID type for clients - just a wrapper around a counter
+// Using a newtype pattern here to avoid mixing up with other u64s
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub struct ConnectionId(pub u64);
+
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// Running totals of what actually went out to one client
+// Shared between the sender handles and the task writing to the socket
+#[derive(Debug, Default)]
+pub struct OutboundStats {
+    messages: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl OutboundStats {
+    // Called by whoever writes to the socket, once per frame sent
+    pub fn record(&self, bytes: usize) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn messages(&self) -> u64 {
+        self.messages.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+}
+
+// Message sender for talking to a specific client
+// Generic over message type so we can use different WS implementations
+#[derive(Clone)]
+pub struct MessageSender<T> {
+    tx: mpsc::Sender<T>,
+    // Separate lane for ephemeral sends, when the receiver drains critical ones first
+    ephemeral_tx: Option<mpsc::Sender<T>>,
+    stats: Arc<OutboundStats>,
+}
+
+impl<T> MessageSender<T>
+where
+    T: Clone + Send + 'static,
+{
+    pub fn new(tx: mpsc::Sender<T>) -> Self {
+        Self {
+            tx,
+            ephemeral_tx: None,
+            stats: Arc::new(OutboundStats::default()),
+        }
+    }
+
+    // Where an ephemeral send goes - its own lane if there is one
+    fn lane(&self, delivery: Delivery) -> &mpsc::Sender<T> {
+        match (delivery, &self.ephemeral_tx) {
+            (Delivery::Ephemeral, Some(ephemeral)) => ephemeral,
+            _ => &self.tx,
+        }
+    }
+
+    // Outbound counters for this client - hand a clone to the socket writer
+    pub fn stats(&self) -> Arc<OutboundStats> {
+        self.stats.clone()
+    }
+
+    // Messages waiting for the send task, and how many the channel(s) can hold
+    pub fn queued(&self) -> (usize, usize) {
+        let lanes = std::iter::once(&self.tx).chain(&self.ephemeral_tx);
+        lanes.fold((0, 0), |(queued, max), tx| {
+            (
+                queued + tx.max_capacity() - tx.capacity(),
+                max + tx.max_capacity(),
+            )
+        })
+    }
+
+    // Resolves once the receiving end is gone, i.e. the send task has finished with the socket
+    pub async fn closed(&self) {
+        self.tx.closed().await
+    }
+
+    // Basic send function - just passes through to the channel
+    // Returns error if the client disconnected
+    pub async fn send(&self, msg: T) -> Result<(), mpsc::error::SendError<T>> {
+        self.tx.send(msg).await
+    }
+
+    /// Queue a message without blocking on a full channel unless it matters.
+    ///
+    /// Ephemeral messages are dropped if the channel is full. Critical ones wait up to
+    /// `critical_wait` for room before giving up. With a sender from [`outbound_channel`],
+    /// ephemeral messages queue separately and critical ones are sent ahead of them.
+    ///
+    /// ```
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// use appstate::{Delivery, MessageSender};
+    /// use std::time::Duration;
+    ///
+    /// let (tx, _rx) = tokio::sync::mpsc::channel(8);
+    /// let sender = MessageSender::new(tx);
+    /// sender.send_with("cursor", Delivery::Ephemeral, Duration::ZERO).await.unwrap();
+    /// sender.send_with("object created", Delivery::Critical, Duration::from_millis(500)).await.unwrap();
+    /// # });
+    /// ```
+    pub async fn send_with(
+        &self,
+        msg: T,
+        delivery: Delivery,
+        critical_wait: Duration,
+    ) -> Result<(), DeliveryError> {
+        match self.lane(delivery).try_send(msg) {
+            Ok(()) => Ok(()),
+            Err(mpsc::error::TrySendError::Closed(_)) => Err(DeliveryError::Disconnected),
+            Err(mpsc::error::TrySendError::Full(msg)) => match delivery {
+                Delivery::Ephemeral => Err(DeliveryError::Dropped),
+                Delivery::Critical => match self.tx.send_timeout(msg, critical_wait).await {
+                    Ok(()) => Ok(()),
+                    Err(mpsc::error::SendTimeoutError::Timeout(_)) => Err(DeliveryError::TimedOut),
+                    Err(mpsc::error::SendTimeoutError::Closed(_)) => {
+                        Err(DeliveryError::Disconnected)
+                    }
+                },
+            },
+        }
+    }
+}
+
+/// A client's outbound queue, optionally split so critical messages jump ahead.
+///
+/// With `prioritize` set, ephemeral sends (see [`MessageSender::send_with`]) get their own
+/// queue of `capacity`, and the receiver only takes from it when nothing critical is waiting.
+/// Order is kept within each class. Everything else - `send`, text, binary, close - is critical.
+///
+/// ```
+/// use appstate::outbound_channel;
+///
+/// // Hand `sender` to the registry and `rx` to the task writing to the socket
+/// let (sender, rx) = outbound_channel::<String>(100, true);
+/// ```
+pub fn outbound_channel<T>(
+    capacity: usize,
+    prioritize: bool,
+) -> (MessageSender<T>, OutboundReceiver<T>)
+where
+    T: Clone + Send + 'static,
+{
+    let (tx, critical) = mpsc::channel(capacity);
+    let mut sender = MessageSender::new(tx);
+    let ephemeral = prioritize.then(|| {
+        let (tx, rx) = mpsc::channel(capacity);
+        sender.ephemeral_tx = Some(tx);
+        rx
+    });
+    (
+        sender,
+        OutboundReceiver {
+            critical,
+            ephemeral,
+        },
+    )
+}
+
+// The send task's end of outbound_channel
+pub struct OutboundReceiver<T> {
+    critical: mpsc::Receiver<T>,
+    ephemeral: Option<mpsc::Receiver<T>>,
+}
+
+impl<T> OutboundReceiver<T> {
+    // Next message, critical first. None once every sender is gone and both queues are empty
+    pub async fn recv(&mut self) -> Option<T> {
+        let Some(ephemeral) = &mut self.ephemeral else {
+            return self.critical.recv().await;
+        };
+        tokio::select! {
+            biased;
+            Some(msg) = self.critical.recv() => Some(msg),
+            Some(msg) = ephemeral.recv() => Some(msg),
+            else => None,
+        }
+    }
+}
+
+// How hard we try to get a message to a client whose queue is backed up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    // Must arrive (object created, close frame...) - wait a bit for room, then give up on the client
+    Critical,
+    // Fine to lose (cursor moves, pings) - dropped on the floor if the queue is full
+    Ephemeral,
+}
+
+// Each message type says which delivery class it belongs to
+// Keeps the decision in one place instead of at every call site
+pub trait Classify {
+    fn delivery(&self) -> Delivery;
+}
+
+// Why a classified send didn't make it into the client's queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryError {
+    Dropped,      // Ephemeral message and the queue was full
+    TimedOut,     // Critical message and the queue stayed full for the whole wait
+    Disconnected, // The client is gone
+}
+
+// Trait to abstract text message creation
+// Needed because different WS implementations have different message types
+pub trait TextMessage {
+    fn create_text_message(text: String) -> Self;
+}
+
+// Same idea but for binary data
+// This lets us avoid hardcoding to axum's message format
+pub trait BinaryMessage {
+    fn create_binary_message(data: Vec<u8>) -> Self;
+}
+
+// Close frames carry a code and a human readable reason
+// Lets the registry hang up on a client properly instead of just dropping the socket
+pub trait CloseMessage {
+    fn create_close_message(code: u16, reason: String) -> Self;
+}
+
+// Close codes we use for server-initiated disconnects (RFC 6455 section 7.4.1)
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_GOING_AWAY: u16 = 1001;
+pub const CLOSE_POLICY_VIOLATION: u16 = 1008;
+pub const CLOSE_TRY_AGAIN_LATER: u16 = 1013;
+
+// How long a broadcast waits on a full queue for a critical message, before hanging up on
+// that client
+pub const BROADCAST_CRITICAL_WAIT: Duration = Duration::from_millis(500);
+
+// Add text sending capabilities if the message type supports it
+// This is conditional - only available if T implements TextMessage
+impl<T> MessageSender<T>
+where
+    T: TextMessage + Send + 'static,
+{
+    // Convenience wrapper for sending text - makes the API nicer
+    pub async fn send_text(
+        &self,
+        text: impl Into<String>,
+    ) -> Result<(), mpsc::error::SendError<T>> {
+        self.tx.send(T::create_text_message(text.into())).await
+    }
+}
+
+// Same pattern for binary - again, only available if T has binary capabilities
+impl<T> MessageSender<T>
+where
+    T: BinaryMessage + Send + 'static,
+{
+    // Send raw bytes to the client
+    pub async fn send_binary(
+        &self,
+        data: impl Into<Vec<u8>>,
+    ) -> Result<(), mpsc::error::SendError<T>> {
+        self.tx.send(T::create_binary_message(data.into())).await
+    }
+}
+
+// Scratch values handlers can stash on a connection, keyed by name
+type ConnectionState = HashMap<String, Box<dyn Any + Send + Sync>>;
+
+// Live slot counts, overall and per source address, shared with every outstanding slot
+// Plain std mutex on purpose - slots give themselves back in Drop where we can't await
+type SlotCounts = Arc<StdMutex<Counts>>;
+
+#[derive(Default)]
+struct Counts {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+// Why a new client wasn't given a slot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SlotError {
+    // The server as a whole is at max_connections
+    Full { limit: usize },
+    TooManyFromIp { ip: IpAddr, limit: usize },
+}
+
+impl fmt::Display for SlotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SlotError::Full { limit } => {
+                write!(f, "server is at its maximum of {} connections", limit)
+            }
+            SlotError::TooManyFromIp { ip, limit } => {
+                write!(f, "{} already has the maximum of {} connections", ip, limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SlotError {}
+
+// A place held for a client that passed the limits but hasn't finished upgrading yet
+// Dropping it - or the connection it gets registered as - gives the place back
+pub struct ConnectionSlot {
+    ip: Option<IpAddr>,
+    counts: SlotCounts,
+}
+
+impl ConnectionSlot {
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.ip
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().expect("Slot count lock poisoned");
+        counts.total -= 1;
+        let Some(ip) = self.ip else { return };
+        if let Some(count) = counts.per_ip.get_mut(&ip) {
+            *count -= 1;
+            // Don't let the map fill up with every address we've ever seen
+            if *count == 0 {
+                counts.per_ip.remove(&ip);
+            }
+        }
+    }
+}
+
+/// Who's on the other end of a connection, for things like "show who's drawing".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionMeta {
+    /// Set once the client has said who it is. Anonymous until then.
+    pub username: Option<String>,
+    pub connected_at: SystemTime,
+    /// The client's address, after trusted-proxy headers are taken into account.
+    pub ip: Option<IpAddr>,
+}
+
+impl ConnectionMeta {
+    // Anonymous, connected just now
+    pub fn new(ip: Option<IpAddr>) -> Self {
+        Self {
+            username: None,
+            connected_at: SystemTime::now(),
+            ip,
+        }
+    }
+}
+
+impl Default for ConnectionMeta {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+// How many errors and closes each connection remembers
+const RECENT_EVENTS: usize = 16;
+// How many closed connections the registry remembers, for chasing down why someone dropped
+const RECENT_CLOSES: usize = 64;
+
+/// Something that went wrong on a connection, or how it ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionEvent {
+    pub at: SystemTime,
+    /// The close code for closes, from either side. `None` for errors.
+    pub close_code: Option<u16>,
+    pub detail: String,
+}
+
+// What a client has been up to lately
+// Shared with the connection's tasks, so recording doesn't need the registry lock
+#[derive(Debug)]
+pub struct ConnectionActivity {
+    last_unix_ms: AtomicU64,
+    events: StdMutex<VecDeque<ConnectionEvent>>,
+}
+
+impl ConnectionActivity {
+    fn new(at: SystemTime) -> Self {
+        let activity = Self {
+            last_unix_ms: AtomicU64::new(0),
+            events: StdMutex::new(VecDeque::new()),
+        };
+        activity.touch(at);
+        activity
+    }
+
+    // Called for every frame the client sends
+    pub fn touch(&self, at: SystemTime) {
+        let unix_ms = at
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+        self.last_unix_ms.store(unix_ms, Ordering::Relaxed);
+    }
+
+    // When the client last sent anything, or when it connected if it never has
+    pub fn last_activity(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.last_unix_ms.load(Ordering::Relaxed))
+    }
+
+    // Only the last RECENT_EVENTS are kept
+    pub fn record(&self, event: ConnectionEvent) {
+        let mut events = self.events.lock().expect("Activity lock poisoned");
+        if events.len() == RECENT_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    // Oldest first
+    pub fn recent_events(&self) -> Vec<ConnectionEvent> {
+        let events = self.events.lock().expect("Activity lock poisoned");
+        events.iter().cloned().collect()
+    }
+}
+
+/// A connection that has gone, with what it recorded on the way out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClosedConnection {
+    pub id: ConnectionId,
+    pub meta: ConnectionMeta,
+    pub last_activity: SystemTime,
+    pub closed_at: SystemTime,
+    /// Errors and closes, oldest first. Ends with the close if either side sent one.
+    pub events: Vec<ConnectionEvent>,
+}
+
+// Everything we track for one client - dropped as a unit on unregister
+struct ConnectionEntry<T> {
+    sender: MessageSender<T>,
+    meta: ConnectionMeta,
+    activity: Arc<ConnectionActivity>,
+    state: ConnectionState,
+    rooms: HashSet<String>, // Mirror of the room map, so cleanup knows where to look
+    _slot: Option<ConnectionSlot>, // Held only so the slot is released with the entry
+}
+
+// The core connection manager - tracks all active clients
+// Using RwLock for better concurrency (many reads, few writes)
+#[derive(Clone)]
+pub struct ConnectionRegistry<T> {
+    connections: Arc<RwLock<HashMap<ConnectionId, ConnectionEntry<T>>>>,
+    next_id: Arc<Mutex<u64>>, // Counter for generating unique IDs
+    slot_counts: SlotCounts,
+    // Room name -> members. Lock order is always connections first, then rooms
+    rooms: Arc<RwLock<HashMap<String, HashSet<ConnectionId>>>>,
+    // Newest last, at most RECENT_CLOSES
+    recent_closes: Arc<StdMutex<VecDeque<ClosedConnection>>>,
+}
+
+impl<T> ConnectionRegistry<T>
+where
+    T: Clone + Send + 'static,
+{
+    // Create fresh registry - start with empty map
+    // Starting IDs at 1 because 0 feels like a sentinel value
+    pub fn new() -> Self {
+        Self {
+            connections: Arc::new(RwLock::new(HashMap::new())),
+            next_id: Arc::new(Mutex::new(1)), // Start IDs from 1
+            slot_counts: Arc::new(StdMutex::new(Counts::default())),
+            rooms: Arc::new(RwLock::new(HashMap::new())),
+            recent_closes: Arc::new(StdMutex::new(VecDeque::new())),
+        }
+    }
+
+    // Add a new connection to the system
+    // Returns its unique ID that can be used to message it later
+    pub async fn register(&self, sender: MessageSender<T>) -> ConnectionId {
+        self.insert(sender, None, ConnectionMeta::default()).await
+    }
+
+    // Same as register, but with metadata known up front
+    pub async fn register_with_meta(
+        &self,
+        sender: MessageSender<T>,
+        meta: ConnectionMeta,
+    ) -> ConnectionId {
+        self.insert(sender, None, meta).await
+    }
+
+    /// Hold a place for a client from `ip` before doing the WebSocket upgrade.
+    ///
+    /// The count checks and the increment happen under one lock, so two clients racing for the
+    /// last place can't both get it. `max_total` caps every slot in the registry, `max_per_ip`
+    /// the ones from a single address; `None` counts without limiting (e.g. for allowlisted
+    /// addresses). Connections added with plain [`register`](Self::register) aren't counted.
+    /// The place is given back when the slot is dropped, or once it's been passed to
+    /// [`register_reserved`](Self::register_reserved), when that connection unregisters.
+    ///
+    /// ```
+    /// use appstate::ConnectionRegistry;
+    ///
+    /// let registry = ConnectionRegistry::<String>::new();
+    /// let ip = "203.0.113.7".parse().ok();
+    /// // At most 10 connections from one address and 1000 overall
+    /// let slot = registry.reserve(ip, Some(1000), Some(10)).unwrap();
+    /// assert_eq!(registry.connections_from(ip.unwrap()), 1);
+    /// ```
+    pub fn reserve(
+        &self,
+        ip: Option<IpAddr>,
+        max_total: Option<usize>,
+        max_per_ip: Option<usize>,
+    ) -> Result<ConnectionSlot, SlotError> {
+        let mut counts = self.slot_counts.lock().expect("Slot count lock poisoned");
+        if let Some(limit) = max_total
+            && counts.total >= limit
+        {
+            return Err(SlotError::Full { limit });
+        }
+        if let Some(ip) = ip {
+            // Check before touching the map, or a refusal would leave a zero count behind
+            let count = counts.per_ip.get(&ip).copied().unwrap_or(0);
+            if let Some(limit) = max_per_ip
+                && count >= limit
+            {
+                return Err(SlotError::TooManyFromIp { ip, limit });
+            }
+            *counts.per_ip.entry(ip).or_insert(0) += 1;
+        }
+        counts.total += 1;
+        Ok(ConnectionSlot {
+            ip,
+            counts: self.slot_counts.clone(),
+        })
+    }
+
+    // Register a connection into a slot handed out by reserve()
+    // The slot lives with the connection so unregistering releases it
+    pub async fn register_reserved(
+        &self,
+        slot: ConnectionSlot,
+        sender: MessageSender<T>,
+    ) -> ConnectionId {
+        let meta = ConnectionMeta::new(slot.ip());
+        self.insert(sender, Some(slot), meta).await
+    }
+
+    // How many live (or reserved) connections come from this address
+    pub fn connections_from(&self, ip: IpAddr) -> usize {
+        let counts = self.slot_counts.lock().expect("Slot count lock poisoned");
+        counts.per_ip.get(&ip).copied().unwrap_or(0)
+    }
+
+    async fn insert(
+        &self,
+        sender: MessageSender<T>,
+        slot: Option<ConnectionSlot>,
+        meta: ConnectionMeta,
+    ) -> ConnectionId {
+        let mut id_guard = self.next_id.lock().await;
+        let id = ConnectionId(*id_guard);
+        *id_guard += 1; // Increment for next time
+
+        let mut connections = self.connections.write().await;
+        connections.insert(
+            id,
+            ConnectionEntry {
+                sender,
+                activity: Arc::new(ConnectionActivity::new(meta.connected_at)),
+                meta,
+                state: HashMap::new(),
+                rooms: HashSet::new(),
+                _slot: slot,
+            },
+        );
+        id
+    }
+
+    // Clean up when a client disconnects
+    // Returns true if we actually removed something
+    pub async fn unregister(&self, id: ConnectionId) -> bool {
+        match self.remove(id).await {
+            Some(entry) => {
+                self.retire(id, &entry, None);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Note a connection that's on its way out in the recent closes, along with the
+    // server's close frame if it sent one
+    fn retire(&self, id: ConnectionId, entry: &ConnectionEntry<T>, close: Option<(u16, &str)>) {
+        let now = SystemTime::now();
+        if let Some((code, reason)) = close {
+            entry.activity.record(ConnectionEvent {
+                at: now,
+                close_code: Some(code),
+                detail: format!("server closed: {}", reason),
+            });
+        }
+        let mut recent = self
+            .recent_closes
+            .lock()
+            .expect("Recent closes lock poisoned");
+        if recent.len() == RECENT_CLOSES {
+            recent.pop_front();
+        }
+        recent.push_back(ClosedConnection {
+            id,
+            meta: entry.meta.clone(),
+            last_activity: entry.activity.last_activity(),
+            closed_at: now,
+            events: entry.activity.recent_events(),
+        });
+    }
+
+    /// The last connections to go, newest first, with the errors and closes they recorded.
+    ///
+    /// ```
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// use appstate::{CLOSE_POLICY_VIOLATION, ConnectionRegistry, MessageSender, OutboundFrame};
+    ///
+    /// let registry = ConnectionRegistry::<OutboundFrame>::new();
+    /// let (tx, _rx) = tokio::sync::mpsc::channel(4);
+    /// let id = registry.register(MessageSender::new(tx)).await;
+    /// registry.close(id, CLOSE_POLICY_VIOLATION, "message too big").await;
+    ///
+    /// let closed = &registry.recent_closes()[0];
+    /// assert_eq!(closed.id, id);
+    /// assert_eq!(closed.events[0].close_code, Some(CLOSE_POLICY_VIOLATION));
+    /// # });
+    /// ```
+    pub fn recent_closes(&self) -> Vec<ClosedConnection> {
+        let recent = self
+            .recent_closes
+            .lock()
+            .expect("Recent closes lock poisoned");
+        recent.iter().rev().cloned().collect()
+    }
+
+    // A connection's activity tracker, for its tasks to record into
+    pub async fn activity(&self, id: ConnectionId) -> Option<Arc<ConnectionActivity>> {
+        let connections = self.connections.read().await;
+        connections.get(&id).map(|entry| entry.activity.clone())
+    }
+
+    // Take a connection out of the map and every room it was in
+    async fn remove(&self, id: ConnectionId) -> Option<ConnectionEntry<T>> {
+        let mut connections = self.connections.write().await;
+        let entry = connections.remove(&id)?;
+        let mut rooms = self.rooms.write().await;
+        for name in &entry.rooms {
+            if let Some(members) = rooms.get_mut(name) {
+                members.remove(&id);
+                // Rooms only exist while someone is in them
+                if members.is_empty() {
+                    rooms.remove(name);
+                }
+            }
+        }
+        Some(entry)
+    }
+
+    /// Put a connection in a named room, creating the room if needed.
+    ///
+    /// Returns false if the connection isn't registered. Unregistering a connection takes it
+    /// out of all its rooms, and a room disappears with its last member.
+    ///
+    /// ```
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// use appstate::{ConnectionRegistry, MessageSender};
+    ///
+    /// let registry = ConnectionRegistry::<String>::new();
+    /// let (tx, _rx) = tokio::sync::mpsc::channel(4);
+    /// let id = registry.register(MessageSender::new(tx)).await;
+    ///
+    /// registry.join_room(id, "canvas-1").await;
+    /// assert_eq!(registry.room_members("canvas-1").await, [id]);
+    /// # });
+    /// ```
+    pub async fn join_room(&self, id: ConnectionId, room: &str) -> bool {
+        let mut connections = self.connections.write().await;
+        let Some(entry) = connections.get_mut(&id) else {
+            return false;
+        };
+        entry.rooms.insert(room.to_string());
+        let mut rooms = self.rooms.write().await;
+        rooms.entry(room.to_string()).or_default().insert(id);
+        true
+    }
+
+    // Take a connection out of one room. Returns false if it wasn't in it
+    pub async fn leave_room(&self, id: ConnectionId, room: &str) -> bool {
+        let mut connections = self.connections.write().await;
+        let Some(entry) = connections.get_mut(&id) else {
+            return false;
+        };
+        if !entry.rooms.remove(room) {
+            return false;
+        }
+        let mut rooms = self.rooms.write().await;
+        if let Some(members) = rooms.get_mut(room) {
+            members.remove(&id);
+            if members.is_empty() {
+                rooms.remove(room);
+            }
+        }
+        true
+    }
+
+    // The rooms a connection is in, sorted. None if it's not connected
+    pub async fn rooms_of(&self, id: ConnectionId) -> Option<Vec<String>> {
+        let connections = self.connections.read().await;
+        let mut rooms: Vec<String> = connections.get(&id)?.rooms.iter().cloned().collect();
+        rooms.sort();
+        Some(rooms)
+    }
+
+    // Who's in a room right now - empty for rooms that don't exist
+    pub async fn room_members(&self, room: &str) -> Vec<ConnectionId> {
+        let rooms = self.rooms.read().await;
+        rooms
+            .get(room)
+            .map(|members| members.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// The `n` busiest rooms with their member counts, busiest first (ties go by name), plus
+    /// how many rooms exist in total.
+    ///
+    /// ```
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// # let registry = appstate::ConnectionRegistry::<String>::new();
+    /// let (rooms, total) = registry.largest_rooms(10).await;
+    /// for (name, members) in rooms {
+    ///     println!("{}: {} members", name, members);
+    /// }
+    /// println!("{} rooms in all", total);
+    /// # });
+    /// ```
+    pub async fn largest_rooms(&self, n: usize) -> (Vec<(String, usize)>, usize) {
+        let rooms = self.rooms.read().await;
+        let mut sizes: Vec<(String, usize)> = rooms
+            .iter()
+            .map(|(name, members)| (name.clone(), members.len()))
+            .collect();
+        sizes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        sizes.truncate(n);
+        (sizes, rooms.len())
+    }
+
+    // Look up a client by ID
+    // Returns None if it doesn't exist/disconnected
+    pub async fn get(&self, id: ConnectionId) -> Option<MessageSender<T>> {
+        let connections = self.connections.read().await;
+        connections.get(&id).map(|entry| entry.sender.clone())
+    }
+
+    /// Stash a value on a connection for handlers that span several messages
+    /// (e.g. a freehand stroke that's still being drawn).
+    ///
+    /// Values are private to the connection and dropped when it unregisters.
+    /// Returns false if the connection doesn't exist.
+    ///
+    /// ```
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// # let registry = appstate::ConnectionRegistry::<String>::new();
+    /// # let (tx, _rx) = tokio::sync::mpsc::channel(8);
+    /// # let id = registry.register(appstate::MessageSender::new(tx)).await;
+    /// registry.set_conn_state(id, "stroke", vec![(1.0, 2.0)]).await;
+    /// let stroke = registry.get_conn_state::<Vec<(f64, f64)>>(id, "stroke").await;
+    /// assert_eq!(stroke, Some(vec![(1.0, 2.0)]));
+    /// # });
+    /// ```
+    pub async fn set_conn_state<V>(
+        &self,
+        id: ConnectionId,
+        key: impl Into<String>,
+        value: V,
+    ) -> bool
+    where
+        V: Any + Send + Sync,
+    {
+        let mut connections = self.connections.write().await;
+        match connections.get_mut(&id) {
+            Some(entry) => {
+                entry.state.insert(key.into(), Box::new(value));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Replace a connection's metadata, e.g. once it has logged in. Returns false if the
+    /// connection doesn't exist.
+    ///
+    /// ```
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// # let registry = appstate::ConnectionRegistry::<String>::new();
+    /// # let (tx, _rx) = tokio::sync::mpsc::channel(8);
+    /// # let id = registry.register(appstate::MessageSender::new(tx)).await;
+    /// let mut meta = registry.get_meta(id).await.unwrap();
+    /// meta.username = Some("alice".to_string());
+    /// registry.set_meta(id, meta).await;
+    /// # });
+    /// ```
+    pub async fn set_meta(&self, id: ConnectionId, meta: ConnectionMeta) -> bool {
+        let mut connections = self.connections.write().await;
+        match connections.get_mut(&id) {
+            Some(entry) => {
+                entry.meta = meta;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // A copy of a connection's metadata, None if it's not connected
+    pub async fn get_meta(&self, id: ConnectionId) -> Option<ConnectionMeta> {
+        let connections = self.connections.read().await;
+        connections.get(&id).map(|entry| entry.meta.clone())
+    }
+
+    // Every connection with its metadata, oldest id first
+    pub async fn list_connections(&self) -> Vec<(ConnectionId, ConnectionMeta)> {
+        let connections = self.connections.read().await;
+        let mut list: Vec<_> = connections
+            .iter()
+            .map(|(id, entry)| (*id, entry.meta.clone()))
+            .collect();
+        list.sort_by_key(|(id, _)| id.0);
+        list
+    }
+
+    // Outbound totals for a client, None if it's not connected
+    pub async fn stats(&self, id: ConnectionId) -> Option<Arc<OutboundStats>> {
+        let connections = self.connections.read().await;
+        connections.get(&id).map(|entry| entry.sender.stats())
+    }
+
+    // Read back a stashed value - None if missing or stored as a different type
+    pub async fn get_conn_state<V>(&self, id: ConnectionId, key: &str) -> Option<V>
+    where
+        V: Any + Clone,
+    {
+        let connections = self.connections.read().await;
+        connections
+            .get(&id)?
+            .state
+            .get(key)?
+            .downcast_ref::<V>()
+            .cloned()
+    }
+
+    // Remove a stashed value and hand it back, e.g. when the stroke is finished
+    // Leaves it in place if the type doesn't match
+    pub async fn take_conn_state<V>(&self, id: ConnectionId, key: &str) -> Option<V>
+    where
+        V: Any,
+    {
+        let mut connections = self.connections.write().await;
+        let state = &mut connections.get_mut(&id)?.state;
+        if !state.get(key)?.is::<V>() {
+            return None;
+        }
+        state.remove(key)?.downcast::<V>().ok().map(|value| *value)
+    }
+
+    // How many clients are currently connected?
+    // Useful for debugging and stats
+    pub async fn count(&self) -> usize {
+        let connections = self.connections.read().await;
+        connections.len()
+    }
+
+    // Get IDs of all connected clients
+    // Useful for iterating through connections when needed
+    pub async fn all_ids(&self) -> Vec<ConnectionId> {
+        let connections = self.connections.read().await;
+        connections.keys().copied().collect()
+    }
+}
+
+// Add text broadcasting if message type supports it
+// Same conditional pattern as with MessageSender
+impl<T> ConnectionRegistry<T>
+where
+    T: TextMessage + Classify + CloseMessage + Clone + Send + 'static,
+{
+    // Simpler API for broadcasting text
+    // This is used a lot, so worth having a dedicated method
+    pub async fn broadcast_text(&self, text: impl Into<String> + Clone) {
+        self.broadcast(T::create_text_message(text.into())).await;
+    }
+
+    // Text to everyone except the sender
+    pub async fn broadcast_text_except(
+        &self,
+        exclude: ConnectionId,
+        text: impl Into<String> + Clone,
+    ) {
+        self.broadcast_except(exclude, T::create_text_message(text.into()))
+            .await;
+    }
+}
+
+// Server-initiated disconnects - only available if T can represent a close frame
+impl<T> ConnectionRegistry<T>
+where
+    T: CloseMessage + Clone + Send + 'static,
+{
+    /// Hang up on a client with a proper close frame instead of just killing the socket.
+    ///
+    /// The close frame is queued behind anything already in the client's channel and the
+    /// connection is unregistered. Since the registry held the last long-lived sender, the
+    /// send task drains the queue, delivers the close frame, and then sees the channel end.
+    /// This never waits: if the queue is full the close frame is skipped, and the connection
+    /// still ends once its send task gets through the queue or gives up on the socket.
+    /// Returns false if the connection was already gone.
+    ///
+    /// ```
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// use appstate::{CLOSE_GOING_AWAY, ConnectionRegistry, MessageSender, OutboundFrame};
+    ///
+    /// let registry = ConnectionRegistry::<OutboundFrame>::new();
+    /// let (tx, _rx) = tokio::sync::mpsc::channel(8);
+    /// let id = registry.register(MessageSender::new(tx)).await;
+    /// assert!(registry.close(id, CLOSE_GOING_AWAY, "server restarting").await);
+    /// # });
+    /// ```
+    pub async fn close(&self, id: ConnectionId, code: u16, reason: impl Into<String>) -> bool {
+        // Out of the map first, so the sender dropped below is the registry's last one
+        let reason = reason.into();
+        let entry = self.remove(id).await;
+        match entry {
+            Some(entry) => {
+                self.retire(id, &entry, Some((code, &reason)));
+                let ConnectionEntry { sender, .. } = entry;
+                // A wedged client must not stall whoever is closing it (heartbeat, shutdown),
+                // so no waiting for room. Gone or full, the sender is dropped here either way.
+                let _ = sender.tx.try_send(T::create_close_message(code, reason));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Close every connection and wait up to `grace` for their send tasks to flush and finish.
+    ///
+    /// Returns how many connections were still flushing when the grace period ran out.
+    ///
+    /// ```
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// use appstate::{CLOSE_GOING_AWAY, ConnectionRegistry, OutboundFrame};
+    /// use std::time::Duration;
+    ///
+    /// let registry = ConnectionRegistry::<OutboundFrame>::new();
+    /// let grace = Duration::from_secs(5);
+    /// let unflushed = registry.close_all(CLOSE_GOING_AWAY, "shutting down", grace).await;
+    /// # assert_eq!(unflushed, 0);
+    /// # });
+    /// ```
+    pub async fn close_all(&self, code: u16, reason: &str, grace: Duration) -> usize {
+        let entries: Vec<_> = {
+            let mut connections = self.connections.write().await;
+            self.rooms.write().await.clear();
+            connections.drain().collect()
+        };
+
+        let mut flushing = JoinSet::new();
+        for (id, entry) in entries {
+            self.retire(id, &entry, Some((code, reason)));
+            let ConnectionEntry { sender, .. } = entry;
+            let close = T::create_close_message(code, reason.to_string());
+            flushing.spawn(async move {
+                let _ = sender.send(close).await;
+                sender.closed().await;
+            });
+        }
+
+        let _ = tokio::time::timeout(grace, async {
+            while flushing.join_next().await.is_some() {}
+        })
+        .await;
+        // Whatever's left gets aborted when the set drops
+        flushing.len()
+    }
+
+    /// Close every connection, spread randomly over `window` so clients don't all come back at once.
+    ///
+    /// Each close reason gets a `reconnect_after_ms=<n>` suffix with its own random backoff
+    /// (also within `window`) that clients should wait before reconnecting. Once the window
+    /// is over, send tasks get up to `grace` more to flush, as in [`close_all`](Self::close_all).
+    ///
+    /// ```
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// use appstate::{CLOSE_GOING_AWAY, ConnectionRegistry, MessageSender};
+    /// use std::time::Duration;
+    /// use utils::random::SeededRandom;
+    ///
+    /// let registry = ConnectionRegistry::<axum::extract::ws::Message>::new();
+    /// let (tx, _rx) = tokio::sync::mpsc::channel(8);
+    /// registry.register(MessageSender::new(tx)).await;
+    ///
+    /// let window = Duration::from_millis(50);
+    /// let closed = registry
+    ///     .close_all_jittered(window, Duration::ZERO, CLOSE_GOING_AWAY, "maintenance", &SeededRandom::new(1))
+    ///     .await;
+    /// assert_eq!(closed.backoffs.len(), 1);
+    /// assert_eq!(registry.count().await, 0);
+    /// # });
+    /// ```
+    pub async fn close_all_jittered(
+        &self,
+        window: Duration,
+        grace: Duration,
+        code: u16,
+        reason: &str,
+        random: &dyn RandomSource,
+    ) -> JitteredClose {
+        let window_ms = window.as_millis().max(1) as u64;
+        let entries: Vec<_> = {
+            let mut connections = self.connections.write().await;
+            self.rooms.write().await.clear();
+            connections.drain().collect()
+        };
+
+        let mut backoffs = Vec::new();
+        let mut flushing = JoinSet::new();
+        for (id, entry) in entries {
+            self.retire(id, &entry, Some((code, reason)));
+            let ConnectionEntry { sender, .. } = entry;
+            let delay = Duration::from_millis(random.next_u64() % window_ms);
+            let reconnect_after_ms = random.next_u64() % window_ms;
+            backoffs.push((id, reconnect_after_ms));
+
+            let reason = format!("{}; reconnect_after_ms={}", reason, reconnect_after_ms);
+            flushing.spawn(async move {
+                tokio::time::sleep(delay).await;
+                // Same as close(): a full queue doesn't get to hold everyone else up
+                let _ = sender.tx.try_send(T::create_close_message(code, reason));
+                sender.closed().await;
+            });
+        }
+
+        let _ = tokio::time::timeout(Duration::from_millis(window_ms) + grace, async {
+            while flushing.join_next().await.is_some() {}
+        })
+        .await;
+        JitteredClose {
+            backoffs,
+            // Whatever's left gets aborted when the set drops
+            unflushed: flushing.len(),
+        }
+    }
+}
+
+/// What [`ConnectionRegistry::close_all_jittered`] did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JitteredClose {
+    /// Each connection and the reconnect backoff it was told, in milliseconds.
+    pub backoffs: Vec<(ConnectionId, u64)>,
+    /// Connections whose send task was still flushing when time ran out.
+    pub unflushed: usize,
+}
+
+// And the same for binary broadcasts
+// Not used as often but good to have for completeness
+impl<T> ConnectionRegistry<T>
+where
+    T: BinaryMessage + Classify + CloseMessage + Clone + Send + 'static,
+{
+    // Send raw bytes to all clients
+    pub async fn broadcast_binary(&self, data: impl Into<Vec<u8>> + Clone) {
+        self.broadcast(T::create_binary_message(data.into())).await;
+    }
+
+    // Raw bytes to everyone except the sender
+    pub async fn broadcast_binary_except(
+        &self,
+        exclude: ConnectionId,
+        data: impl Into<Vec<u8>> + Clone,
+    ) {
+        self.broadcast_except(exclude, T::create_binary_message(data.into()))
+            .await;
+    }
+}
+
+// Broadcasts - Classify picks the delivery policy, CloseMessage lets us hang up on clients
+// that can't keep up instead of letting them fall silently out of sync
+impl<T> ConnectionRegistry<T>
+where
+    T: Classify + CloseMessage + Clone + Send + 'static,
+{
+    // Send the same message to all connected clients
+    // Ephemeral messages are dropped for clients with a full queue, critical ones wait
+    // up to BROADCAST_CRITICAL_WAIT and then the client is closed
+    pub async fn broadcast(&self, msg: T) {
+        self.broadcast_classified(msg, BROADCAST_CRITICAL_WAIT)
+            .await;
+    }
+
+    /// Broadcast to everyone but `exclude`, usually the client the update came from.
+    ///
+    /// ```
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// use appstate::{ConnectionRegistry, MessageSender, OutboundFrame};
+    ///
+    /// let registry = ConnectionRegistry::<OutboundFrame>::new();
+    /// let (tx, _rx) = tokio::sync::mpsc::channel(4);
+    /// let author = registry.register(MessageSender::new(tx)).await;
+    ///
+    /// registry.broadcast_except(author, OutboundFrame::Text("stroke".into())).await;
+    /// # });
+    /// ```
+    pub async fn broadcast_except(&self, exclude: ConnectionId, msg: T) {
+        self.deliver(msg, BROADCAST_CRITICAL_WAIT, |id| id != exclude)
+            .await;
+    }
+
+    /// Send a message to everyone in a room. Unknown rooms are just empty, not an error.
+    ///
+    /// ```
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// use appstate::{ConnectionRegistry, MessageSender, OutboundFrame};
+    ///
+    /// let registry = ConnectionRegistry::<OutboundFrame>::new();
+    /// let (tx, _rx) = tokio::sync::mpsc::channel(4);
+    /// let id = registry.register(MessageSender::new(tx)).await;
+    /// registry.join_room(id, "canvas-1").await;
+    ///
+    /// registry.broadcast_to_room("canvas-1", OutboundFrame::Text("stroke".into())).await;
+    /// # });
+    /// ```
+    pub async fn broadcast_to_room(&self, room: &str, msg: T) {
+        // Copy the members out so the rooms lock isn't held across the sends
+        let Some(members) = self.rooms.read().await.get(room).cloned() else {
+            return;
+        };
+        self.deliver(msg, BROADCAST_CRITICAL_WAIT, |id| members.contains(&id))
+            .await;
+    }
+
+    /// Send to just the connections in `ids`, under one read lock instead of a `get` each.
+    ///
+    /// Delivery works like a broadcast, so a client that can't take a critical message within
+    /// [`BROADCAST_CRITICAL_WAIT`] is closed rather than waited on. Ids that aren't connected
+    /// (any more) are skipped. Returns how many sends went through.
+    ///
+    /// ```
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// # use appstate::OutboundFrame;
+    /// # let registry = appstate::ConnectionRegistry::<OutboundFrame>::new();
+    /// # let (tx, _rx) = tokio::sync::mpsc::channel(8);
+    /// # let selected = [registry.register(appstate::MessageSender::new(tx)).await];
+    /// let sent = registry.send_to(&selected, OutboundFrame::Text("selection changed".into())).await;
+    /// # assert_eq!(sent, 1);
+    /// # });
+    /// ```
+    pub async fn send_to(&self, ids: &[ConnectionId], msg: T) -> usize {
+        self.deliver(msg, BROADCAST_CRITICAL_WAIT, |id| ids.contains(&id))
+            .await
+            .0
+    }
+
+    // Broadcast with an explicit wait for critical messages
+    // Returns the ids we gave up on
+    pub async fn broadcast_classified(&self, msg: T, critical_wait: Duration) -> Vec<ConnectionId> {
+        self.deliver(msg, critical_wait, |_| true).await.1
+    }
+
+    // Shared by every broadcast: send to each connection `include` accepts with its delivery
+    // class, then close the ones that timed out on a critical message - they'd be out of sync.
+    // Returns how many sends went through and the ids that were closed
+    async fn deliver(
+        &self,
+        msg: T,
+        critical_wait: Duration,
+        include: impl Fn(ConnectionId) -> bool,
+    ) -> (usize, Vec<ConnectionId>) {
+        let delivery = msg.delivery();
+        let mut sent = 0;
+        let mut too_slow = Vec::new();
+        {
+            let connections = self.connections.read().await;
+            for (id, entry) in connections.iter() {
+                if !include(*id) {
+                    continue;
+                }
+                // Dropped and Disconnected are fine - gone clients are cleaned up elsewhere
+                match entry
+                    .sender
+                    .send_with(msg.clone(), delivery, critical_wait)
+                    .await
+                {
+                    Ok(()) => sent += 1,
+                    Err(DeliveryError::TimedOut) => too_slow.push(*id),
+                    Err(_) => {}
+                }
+            }
+        }
+
+        // Close outside the read lock - close() needs the write lock
+        for id in &too_slow {
+            self.close(*id, CLOSE_POLICY_VIOLATION, "too slow to keep up")
+                .await;
+        }
+        (sent, too_slow)
+    }
+}
+
+// Implement Default so we can use this with struct field defaults
+// Just delegates to new() to avoid duplicating logic
+impl<T> Default for ConnectionRegistry<T>
+where
+    T: Clone + Send + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::ws::Message;
+
+    async fn registered(
+        registry: &ConnectionRegistry<Message>,
+        capacity: usize,
+    ) -> (ConnectionId, mpsc::Receiver<Message>) {
+        let (tx, rx) = mpsc::channel(capacity);
+        (registry.register(MessageSender::new(tx)).await, rx)
+    }
+
+    #[tokio::test]
+    async fn close_does_not_wait_on_a_full_queue() {
+        let registry = ConnectionRegistry::<Message>::new();
+        let (id, mut rx) = registered(&registry, 1).await;
+        let sender = registry.get(id).await.unwrap();
+        sender.send(Message::Text("backlog".into())).await.unwrap();
+        drop(sender);
+
+        let closed = tokio::time::timeout(
+            Duration::from_secs(1),
+            registry.close(id, CLOSE_POLICY_VIOLATION, "wedged"),
+        )
+        .await
+        .expect("close blocked on a full queue");
+        assert!(closed);
+        assert!(registry.get(id).await.is_none());
+
+        // The queued message still drains, then the channel ends without a close frame
+        assert!(matches!(rx.recv().await, Some(Message::Text(_))));
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn jittered_close_spreads_closes_and_counts_wedged_clients() {
+        let registry = ConnectionRegistry::<Message>::new();
+        let mut receivers = Vec::new();
+        for _ in 0..4 {
+            receivers.push(registered(&registry, 8).await.1);
+        }
+        // Never read, so it never finishes flushing
+        let (_wedged, _stuck) = registered(&registry, 8).await;
+        let readers: Vec<_> = receivers
+            .into_iter()
+            .map(|mut rx| {
+                tokio::spawn(async move {
+                    let started = tokio::time::Instant::now();
+                    match rx.recv().await {
+                        Some(Message::Close(Some(frame))) => (started.elapsed(), frame.reason),
+                        other => panic!("expected a close frame, got {:?}", other),
+                    }
+                })
+            })
+            .collect();
+
+        let window = Duration::from_millis(200);
+        let closed = registry
+            .close_all_jittered(
+                window,
+                Duration::from_millis(20),
+                CLOSE_GOING_AWAY,
+                "maintenance",
+                &utils::random::SeededRandom::new(7),
+            )
+            .await;
+        assert_eq!(registry.count().await, 0);
+        assert_eq!(closed.unflushed, 1);
+        assert_eq!(closed.backoffs.len(), 5);
+        assert!(closed.backoffs.iter().all(|(_, ms)| *ms < 200));
+
+        let mut arrivals = Vec::new();
+        for reader in readers {
+            let (after, reason) = reader.await.unwrap();
+            assert!(reason.starts_with("maintenance; reconnect_after_ms="));
+            arrivals.push(after);
+        }
+        arrivals.sort();
+        assert!(arrivals.last().unwrap() > arrivals.first().unwrap());
+    }
+
+    #[tokio::test]
+    async fn room_and_except_broadcasts_pick_their_targets() {
+        let registry = ConnectionRegistry::<Message>::new();
+        let (a, mut rx_a) = registered(&registry, 4).await;
+        let (b, mut rx_b) = registered(&registry, 4).await;
+        let (_c, mut rx_c) = registered(&registry, 4).await;
+        registry.join_room(a, "canvas-1").await;
+        registry.join_room(b, "canvas-2").await;
+
+        registry
+            .broadcast_to_room("canvas-1", Message::Text("stroke".into()))
+            .await;
+        registry
+            .broadcast_to_room("nobody-here", Message::Text("ignored".into()))
+            .await;
+        assert!(matches!(rx_a.try_recv(), Ok(Message::Text(t)) if t == "stroke"));
+        assert!(rx_b.try_recv().is_err());
+        assert!(rx_c.try_recv().is_err());
+
+        registry.leave_room(a, "canvas-1").await;
+        registry
+            .broadcast_to_room("canvas-1", Message::Text("stroke".into()))
+            .await;
+        assert!(rx_a.try_recv().is_err());
+
+        registry.broadcast_text_except(a, "undo").await;
+        assert!(rx_a.try_recv().is_err());
+        assert!(matches!(rx_b.try_recv(), Ok(Message::Text(t)) if t == "undo"));
+        assert!(matches!(rx_c.try_recv(), Ok(Message::Text(t)) if t == "undo"));
+    }
+
+    #[tokio::test]
+    async fn broadcast_closes_clients_too_slow_for_critical_messages() {
+        let registry = ConnectionRegistry::<Message>::new();
+        let (fast, mut rx_fast) = registered(&registry, 4).await;
+        let (slow, _rx_slow) = registered(&registry, 1).await;
+        let backlog = registry.get(slow).await.unwrap();
+        backlog.send(Message::Text("backlog".into())).await.unwrap();
+        drop(backlog);
+
+        // A full queue just loses ephemeral messages
+        let wait = Duration::from_millis(20);
+        let gave_up = registry
+            .broadcast_classified(Message::Ping(Default::default()), wait)
+            .await;
+        assert!(gave_up.is_empty());
+        assert!(registry.get(slow).await.is_some());
+
+        // ...but a critical one that doesn't fit in time costs the client its connection
+        let gave_up = registry
+            .broadcast_classified(Message::Text("object created".into()), wait)
+            .await;
+        assert_eq!(gave_up, vec![slow]);
+        assert!(registry.get(slow).await.is_none());
+        assert!(registry.get(fast).await.is_some());
+        assert!(matches!(rx_fast.try_recv(), Ok(Message::Ping(_))));
+        assert!(matches!(rx_fast.try_recv(), Ok(Message::Text(t)) if t == "object created"));
+    }
+
+    #[tokio::test]
+    async fn activity_and_close_history_stay_bounded() {
+        let registry = ConnectionRegistry::<Message>::new();
+        let (id, _rx) = registered(&registry, 4).await;
+        let activity = registry.activity(id).await.unwrap();
+        for n in 0..RECENT_EVENTS + 3 {
+            activity.record(ConnectionEvent {
+                at: SystemTime::now(),
+                close_code: None,
+                detail: format!("error {}", n),
+            });
+        }
+        let events = activity.recent_events();
+        assert_eq!(events.len(), RECENT_EVENTS);
+        assert_eq!(events[0].detail, "error 3");
+
+        let later = SystemTime::now() + Duration::from_secs(60);
+        activity.touch(later);
+        registry.unregister(id).await;
+        let closed = &registry.recent_closes()[0];
+        assert_eq!(closed.id, id);
+        assert_eq!(
+            closed
+                .last_activity
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis(),
+            later.duration_since(UNIX_EPOCH).unwrap().as_millis()
+        );
+        // No close frame from us, so the history ends with what the connection recorded
+        assert_eq!(
+            closed.events.last().unwrap().detail,
+            format!("error {}", RECENT_EVENTS + 2)
+        );
+
+        for _ in 0..RECENT_CLOSES {
+            let (id, _rx) = registered(&registry, 4).await;
+            registry.close(id, CLOSE_GOING_AWAY, "bye").await;
+        }
+        let recent = registry.recent_closes();
+        assert_eq!(recent.len(), RECENT_CLOSES);
+        assert!(recent.iter().all(|closed| closed.id != id));
+    }
+
+    #[test]
+    fn refused_reservations_leave_no_per_ip_entry() {
+        let registry = ConnectionRegistry::<Message>::new();
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        assert_eq!(
+            registry.reserve(Some(ip), None, Some(0)).err(),
+            Some(SlotError::TooManyFromIp { ip, limit: 0 })
+        );
+        assert!(registry.slot_counts.lock().unwrap().per_ip.is_empty());
+        assert_eq!(registry.slot_counts.lock().unwrap().total, 0);
+    }
+
+    #[tokio::test]
+    async fn send_with_drops_ephemeral_and_waits_for_critical() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let sender = MessageSender::new(tx);
+        let wait = Duration::from_secs(1);
+        sender
+            .send_with("first", Delivery::Critical, wait)
+            .await
+            .unwrap();
+
+        // The queue is full, so a cursor update is just dropped...
+        assert_eq!(
+            sender.send_with("cursor", Delivery::Ephemeral, wait).await,
+            Err(DeliveryError::Dropped)
+        );
+
+        // ...but a critical message waits for the slow client to catch up
+        let reader = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            (rx.recv().await, rx.recv().await)
+        });
+        sender
+            .send_with("object created", Delivery::Critical, wait)
+            .await
+            .unwrap();
+        assert_eq!(
+            reader.await.unwrap(),
+            (Some("first"), Some("object created"))
+        );
+    }
+
+    #[tokio::test]
+    async fn prioritized_channels_send_critical_messages_first() {
+        let (sender, mut rx) = outbound_channel::<&str>(8, true);
+        for cursor in ["cursor 1", "cursor 2", "cursor 3"] {
+            sender
+                .send_with(cursor, Delivery::Ephemeral, Duration::ZERO)
+                .await
+                .unwrap();
+        }
+        sender.send("object created").await.unwrap();
+
+        assert_eq!(rx.recv().await, Some("object created"));
+        assert_eq!(rx.recv().await, Some("cursor 1"));
+        assert_eq!(rx.recv().await, Some("cursor 2"));
+        assert_eq!(rx.recv().await, Some("cursor 3"));
+
+        // Without it, it's one first-in-first-out queue
+        let (sender, mut rx) = outbound_channel::<&str>(8, false);
+        sender
+            .send_with("cursor", Delivery::Ephemeral, Duration::ZERO)
+            .await
+            .unwrap();
+        sender.send("object created").await.unwrap();
+        assert_eq!(rx.recv().await, Some("cursor"));
+    }
+
+    #[test]
+    fn reservations_are_capped_per_address_and_overall() {
+        let registry = ConnectionRegistry::<String>::new();
+        let home: IpAddr = "203.0.113.7".parse().unwrap();
+        let office: IpAddr = "198.51.100.1".parse().unwrap();
+
+        let first = registry.reserve(Some(home), None, Some(2)).unwrap();
+        let _second = registry.reserve(Some(home), None, Some(2)).unwrap();
+        assert_eq!(
+            registry.reserve(Some(home), None, Some(2)).err(),
+            Some(SlotError::TooManyFromIp { ip: home, limit: 2 })
+        );
+
+        // Other addresses have their own budget
+        let _third = registry.reserve(Some(office), None, Some(2)).unwrap();
+
+        // Giving a slot back makes room again
+        drop(first);
+        assert_eq!(registry.connections_from(home), 1);
+        let fourth = registry.reserve(Some(home), None, Some(2)).unwrap();
+
+        // With three slots held, a server-wide cap of three turns everyone away
+        assert_eq!(
+            registry.reserve(Some(office), Some(3), Some(2)).err(),
+            Some(SlotError::Full { limit: 3 })
+        );
+        drop(fourth);
+        assert!(registry.reserve(Some(office), Some(3), Some(2)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn unregistering_leaves_every_room() {
+        let registry = ConnectionRegistry::<Message>::new();
+        let (id, _rx) = registered(&registry, 4).await;
+
+        assert!(registry.join_room(id, "canvas-1").await);
+        assert!(registry.join_room(id, "canvas-2").await);
+        assert_eq!(registry.room_members("canvas-1").await, [id]);
+
+        registry.unregister(id).await;
+        assert!(registry.room_members("canvas-1").await.is_empty());
+        assert!(registry.room_members("canvas-2").await.is_empty());
+        assert!(!registry.join_room(id, "canvas-1").await);
+    }
+
+    #[tokio::test]
+    async fn largest_rooms_go_by_size_then_name() {
+        let registry = ConnectionRegistry::<Message>::new();
+        let mut ids = Vec::new();
+        let mut receivers = Vec::new();
+        for _ in 0..3 {
+            let (id, rx) = registered(&registry, 8).await;
+            ids.push(id);
+            receivers.push(rx);
+        }
+        for id in &ids {
+            registry.join_room(*id, "busy").await;
+        }
+        registry.join_room(ids[0], "quiet").await;
+        registry.join_room(ids[1], "lonely").await;
+
+        assert_eq!(
+            registry.largest_rooms(2).await,
+            (vec![("busy".to_string(), 3), ("lonely".to_string(), 1)], 3)
+        );
+
+        registry.leave_room(ids[2], "busy").await;
+        registry.unregister(ids[1]).await;
+        assert_eq!(
+            registry.largest_rooms(5).await,
+            (vec![("busy".to_string(), 1), ("quiet".to_string(), 1)], 2)
+        );
+    }
+
+    #[tokio::test]
+    async fn conn_state_is_per_connection_typed_and_dropped_on_unregister() {
+        let registry = ConnectionRegistry::<Message>::new();
+        let (a, _rx_a) = registered(&registry, 8).await;
+        let (b, _rx_b) = registered(&registry, 8).await;
+
+        assert!(registry.set_conn_state(a, "stroke", vec![(1.0, 2.0)]).await);
+        assert_eq!(
+            registry
+                .get_conn_state::<Vec<(f64, f64)>>(a, "stroke")
+                .await,
+            Some(vec![(1.0, 2.0)])
+        );
+        // Other connections can't see it, and asking for the wrong type gets nothing
+        assert_eq!(
+            registry
+                .get_conn_state::<Vec<(f64, f64)>>(b, "stroke")
+                .await,
+            None
+        );
+        assert_eq!(registry.get_conn_state::<String>(a, "stroke").await, None);
+
+        registry.unregister(a).await;
+        assert_eq!(
+            registry
+                .get_conn_state::<Vec<(f64, f64)>>(a, "stroke")
+                .await,
+            None
+        );
+        assert!(!registry.set_conn_state(a, "stroke", 1).await);
+    }
+
+    #[tokio::test]
+    async fn meta_can_be_replaced_and_goes_with_the_connection() {
+        let registry = ConnectionRegistry::<Message>::new();
+        let (tx, _rx_a) = mpsc::channel(8);
+        let ip = "203.0.113.7".parse().ok();
+        let a = registry
+            .register_with_meta(MessageSender::new(tx), ConnectionMeta::new(ip))
+            .await;
+        let (b, _rx_b) = registered(&registry, 8).await;
+
+        let mut meta = registry.get_meta(a).await.unwrap();
+        assert_eq!((meta.ip, meta.username.as_deref()), (ip, None));
+        meta.username = Some("alice".to_string());
+        assert!(registry.set_meta(a, meta).await);
+
+        let names: Vec<_> = registry
+            .list_connections()
+            .await
+            .into_iter()
+            .map(|(id, meta)| (id, meta.username))
+            .collect();
+        assert_eq!(names, [(a, Some("alice".to_string())), (b, None)]);
+
+        registry.unregister(a).await;
+        assert_eq!(registry.get_meta(a).await, None);
+        assert!(!registry.set_meta(a, ConnectionMeta::default()).await);
+    }
+
+    #[tokio::test]
+    async fn send_to_skips_ids_that_are_not_connected() {
+        let registry = ConnectionRegistry::<Message>::new();
+        let (a, mut rx_a) = registered(&registry, 4).await;
+        let (b, mut rx_b) = registered(&registry, 4).await;
+        let (_c, mut rx_c) = registered(&registry, 4).await;
+        registry.unregister(b).await;
+
+        let msg = Message::Text("selection changed".into());
+        assert_eq!(registry.send_to(&[a, b, ConnectionId(999)], msg).await, 1);
+        assert!(matches!(rx_a.try_recv(), Ok(Message::Text(t)) if t == "selection changed"));
+        assert!(rx_b.try_recv().is_err());
+        assert!(rx_c.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn send_to_a_wedged_client_does_not_block_the_registry() {
+        let registry = Arc::new(ConnectionRegistry::<Message>::new());
+        let (wedged, _rx) = registered(&registry, 1).await;
+        let backlog = registry.get(wedged).await.unwrap();
+        backlog.send(Message::Text("backlog".into())).await.unwrap();
+        drop(backlog);
+
+        let sending = tokio::spawn({
+            let registry = registry.clone();
+            async move {
+                let msg = Message::Text("selection changed".into());
+                registry.send_to(&[wedged], msg).await
+            }
+        });
+        // Let the send start waiting on the full queue before registering behind it
+        tokio::task::yield_now().await;
+        let (other, _rx_other) =
+            tokio::time::timeout(Duration::from_secs(1), registered(&registry, 4))
+                .await
+                .expect("register waited on a wedged client");
+
+        assert_eq!(sending.await.unwrap(), 0);
+        assert!(registry.get(wedged).await.is_none());
+        assert!(registry.get(other).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn close_sends_a_close_frame_and_ends_the_channel() {
+        let registry = ConnectionRegistry::<Message>::new();
+        let (id, mut rx) = registered(&registry, 8).await;
+
+        assert!(
+            registry
+                .close(id, CLOSE_GOING_AWAY, "server restarting")
+                .await
+        );
+        match rx.recv().await {
+            Some(Message::Close(Some(frame))) => assert_eq!(frame.code, CLOSE_GOING_AWAY),
+            other => panic!("expected a close frame, got {:?}", other),
+        }
+        // Nothing else is holding the sender, so the channel ends right after the close frame
+        assert!(rx.recv().await.is_none());
+        assert!(!registry.close(id, CLOSE_GOING_AWAY, "again").await);
+    }
+
+    #[tokio::test]
+    async fn close_all_counts_the_connections_still_flushing() {
+        let registry = ConnectionRegistry::<Message>::new();
+        // A client whose send task delivers the close frame and exits
+        let (_, mut rx) = registered(&registry, 8).await;
+        tokio::spawn(async move {
+            while rx
+                .recv()
+                .await
+                .is_some_and(|msg| !matches!(msg, Message::Close(_)))
+            {}
+        });
+        // And one whose socket is wedged
+        let (_, _stuck) = registered(&registry, 8).await;
+
+        let grace = Duration::from_millis(50);
+        assert_eq!(
+            registry
+                .close_all(CLOSE_GOING_AWAY, "shutting down", grace)
+                .await,
+            1
+        );
+        assert_eq!(registry.count().await, 0);
+    }
+}
@@ -1,37 +1,195 @@
+mod overrides;
+#[cfg(test)]
+mod test_support;
+mod validate;
+mod watch;
+
 use serde::{Deserialize, Serialize};
-use std::{fs, path::Path};
+use std::{
+    fmt, fs,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+pub use overrides::{
+    CliOverrides, ENV_ADMIN_TOKEN, ENV_DATABASE_PATH, ENV_INTERFACE, ENV_PORT, EnvOverrideError,
+};
+pub use validate::{ConfigIssue, ConfigValidationError, INTERFACE_ALIASES, IssueSeverity};
+pub use watch::{ConfigLoadError, try_load_config, watch_config};
+
+// How long the "create a config file?" prompt waits before picking JSON on its own
+const FORMAT_PROMPT_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct Config {
     pub network: InterfaceConfig,
+    pub limits: LimitsConfig,
+    pub branding: BrandingConfig,
+    pub logging: LoggingConfig,
+    pub heartbeat: HeartbeatConfig,
     pub database_path: String,
+    /// Selects the storage backend by URL (e.g. `sqlite://canvas.db`). Takes precedence over
+    /// `database_path` when set.
+    pub database_url: Option<String>,
+    /// Where reads go, in the same form as `database_url`, e.g. a replicated copy. Writes
+    /// always use the primary. Reads use the primary too when unset.
+    pub read_database_url: Option<String>,
+    /// How long to wait on a database locked by another process before giving up.
+    pub database_busy_timeout_ms: u64,
+    /// Most SQLite connections kept open at once. Queries beyond this wait for a free one.
+    pub database_pool_size: u32,
+    /// How long connections get to receive their close frame on shutdown before we exit anyway.
+    pub shutdown_grace_secs: u64,
+    /// Close frames on shutdown or at the end of a drain are spread randomly over this window,
+    /// so clients don't all reconnect in the same instant.
+    pub disconnect_jitter_ms: u64,
+    /// Pretty-print JSON when writing the config file. API responses are always compact.
+    pub json_pretty: bool,
+    /// Serve the web client from this directory instead of the copy built into the binary.
+    /// Files missing from it still come from the built-in copy. Meant for frontend work.
+    pub static_dir: Option<String>,
+    /// Serve HTTPS/WSS directly with this certificate. Plain HTTP when unset.
+    pub tls: Option<TlsConfig>,
+    /// Send each client's critical frames ahead of queued ephemeral ones (pings, cursor moves).
+    /// Off means a single first-in-first-out queue.
+    pub prioritize_critical_messages: bool,
+    /// How many times a background task (like the webserver) is restarted before the
+    /// process gives up and exits.
+    pub task_max_restarts: u32,
+    /// Wait before the first restart of a failed task. Doubles with each restart after.
+    pub task_restart_backoff_ms: u64,
+    /// Where SIGUSR1 writes a diagnostic snapshot of the running server. Overwritten each time.
+    pub snapshot_path: String,
+    /// Required as `Authorization: Bearer <token>` on the `/admin` endpoints, which stay
+    /// disabled while this is unset. Better set through `RUSTCANVAS_ADMIN_TOKEN` than the file.
+    pub admin_token: Option<String>,
 }
+#[derive(Clone, Copy, PartialEq, Eq)]
 enum ConfigTypes {
     Toml,
     Json,
     None,
 }
 
+// Recognized config files, most preferred first - the first one that exists is used
+const CONFIG_FORMATS: [(&str, ConfigTypes); 2] =
+    [("json", ConfigTypes::Json), ("toml", ConfigTypes::Toml)];
+
+// A path ending in .json or .toml names that one file, in that format
+fn explicit_format(path: &str) -> Option<ConfigTypes> {
+    CONFIG_FORMATS
+        .into_iter()
+        .find(|(ext, _)| {
+            path.strip_suffix(ext)
+                .is_some_and(|stem| stem.ends_with('.'))
+        })
+        .map(|(_, kind)| kind)
+}
+
+// `path` without its config extension, if it has one
+fn config_base(path: &str) -> &str {
+    match explicit_format(path) {
+        Some(_) => &path[..path.rfind('.').unwrap_or(path.len())],
+        None => path,
+    }
+}
+
+// The file of format `kind` that `path` refers to
+fn config_file_path(path: &str, kind: ConfigTypes) -> String {
+    if explicit_format(path).is_some() {
+        return path.to_string();
+    }
+    let ext = CONFIG_FORMATS
+        .into_iter()
+        .find(|(_, format)| *format == kind)
+        .map_or("json", |(ext, _)| ext);
+    format!("{}.{}", path, ext)
+}
+
+// Every recognized config file that exists for `file_name`, in order of preference.
+// An explicit file name is the only candidate, even if other formats sit next to it
+fn config_candidates(file_name: &str) -> Vec<(ConfigTypes, PathBuf)> {
+    if let Some(kind) = explicit_format(file_name) {
+        let path = PathBuf::from(file_name);
+        return if path.exists() {
+            vec![(kind, path)]
+        } else {
+            Vec::new()
+        };
+    }
+    CONFIG_FORMATS
+        .into_iter()
+        .map(|(ext, kind)| (kind, PathBuf::from(format!("{}.{}", file_name, ext))))
+        .filter(|(_, path)| path.exists())
+        .collect()
+}
+
 fn find_config_type(file_name: &str) -> ConfigTypes {
-    let json_file = format!("{}.json", file_name);
-    let toml_file = format!("{}.toml", file_name);
+    config_candidates(file_name)
+        .into_iter()
+        .next()
+        .map_or(ConfigTypes::None, |(kind, _)| kind)
+}
 
-    let json_path = Path::new(&json_file);
-    let toml_path = Path::new(&toml_file);
+/// Which config file a path resolves to, and the ones passed over for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigSelection {
+    /// The file that gets loaded, if any exists.
+    pub chosen: Option<PathBuf>,
+    /// Other recognized config files that exist but are not read.
+    pub ignored: Vec<PathBuf>,
+}
 
-    if json_path.exists() {
-        ConfigTypes::Json
-    } else if toml_path.exists() {
-        ConfigTypes::Toml
-    } else {
-        ConfigTypes::None
+impl ConfigSelection {
+    /// The warning [`load_config`] logs when more than one config file exists, if it does.
+    pub fn conflict_warning(&self) -> Option<String> {
+        let chosen = self.chosen.as_ref()?;
+        if self.ignored.is_empty() {
+            return None;
+        }
+        let ignored: Vec<String> = self
+            .ignored
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        Some(format!(
+            "Found more than one config file, using {} and ignoring {}",
+            chosen.display(),
+            ignored.join(", ")
+        ))
+    }
+}
+
+/// Find the config file for `path`, noting any others that exist too.
+///
+/// A `path` ending in `.json` or `.toml` only ever finds that file. For a base name, JSON is
+/// preferred over TOML. Having both is almost always a leftover, and edits to the
+/// ignored one silently do nothing, so [`load_config`] warns about it.
+///
+/// # Example
+/// ```
+/// let selection = config::select_config_file("config");
+/// if let Some(warning) = selection.conflict_warning() {
+///     eprintln!("{}", warning);
+/// }
+/// ```
+pub fn select_config_file(path: &str) -> ConfigSelection {
+    let mut found = config_candidates(path).into_iter().map(|(_, path)| path);
+    ConfigSelection {
+        chosen: found.next(),
+        ignored: found.collect(),
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct InterfaceConfig {
     pub interface: String,
     pub port: u16,
+    /// Reverse proxies whose forwarding headers we believe when working out client addresses.
+    pub trusted_proxies: Vec<IpAddr>,
 }
 
 impl Default for InterfaceConfig {
@@ -39,73 +197,435 @@ impl Default for InterfaceConfig {
         Self {
             interface: "0.0.0.0".to_string(),
             port: 3250,
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+/// PEM files for serving TLS without a reverse proxy.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// Certificate chain, leaf first.
+    pub cert_path: String,
+    pub key_path: String,
+}
+
+/// Name and colors deployments can use to brand the installable web app.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct BrandingConfig {
+    pub app_name: String,
+    pub short_name: String,
+    pub theme_color: String,
+    pub background_color: String,
+}
+
+impl Default for BrandingConfig {
+    fn default() -> Self {
+        Self {
+            app_name: "RustCanvas".to_string(),
+            short_name: "RustCanvas".to_string(),
+            theme_color: "#ce422b".to_string(),
+            background_color: "#ffffff".to_string(),
+        }
+    }
+}
+
+/// Knobs for the noisier log lines.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Most bytes of a binary frame dumped in hex by the trace log.
+    pub trace_max_bytes: usize,
+    /// Only trace one binary frame in this many per connection.
+    pub trace_sample_every: u64,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            trace_max_bytes: 64,
+            trace_sample_every: 10,
+        }
+    }
+}
+
+/// WebSocket keepalive timing.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct HeartbeatConfig {
+    /// Seconds between pings sent to each client.
+    pub ping_interval_secs: u64,
+    /// Seconds without a pong before a client is dropped. Must be longer than the ping interval.
+    pub timeout_secs: u64,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        // 30s suits most clients & proxies, and 3x that rides out a couple of lost pongs
+        Self {
+            ping_interval_secs: 30,
+            timeout_secs: 90,
+        }
+    }
+}
+
+/// Caps that protect the server from a single client or a flood of them.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Largest text or binary message a client may send. Bigger ones close the connection
+    /// with a policy violation before the whole thing is buffered.
+    pub max_message_bytes: usize,
+    /// Most concurrent WebSocket connections overall. New upgrades get a 503 past this.
+    /// Unlimited when unset.
+    pub max_connections: Option<usize>,
+    /// Most concurrent WebSocket connections one IP may hold. Unlimited when unset.
+    pub max_connections_per_ip: Option<usize>,
+    /// Addresses exempt from the per-IP cap and connect rate, e.g. an office NAT.
+    pub per_ip_allowlist: Vec<IpAddr>,
+    /// How fast one IP may open new WebSocket connections. Over-limit upgrades get a 429.
+    /// Unlimited when unset.
+    pub connect_rate: Option<ConnectRate>,
+    /// Lifetime cap on what the server sends a single connection. Unlimited when unset.
+    pub outbound_quota: Option<OutboundQuota>,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_message_bytes: 16 * 1024 * 1024,
+            max_connections: None,
+            max_connections_per_ip: None,
+            per_ip_allowlist: Vec::new(),
+            connect_rate: None,
+            outbound_quota: None,
         }
     }
 }
 
+/// A token bucket per IP: `burst` connections at once, refilled at `per_minute`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct ConnectRate {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+impl Default for ConnectRate {
+    fn default() -> Self {
+        Self {
+            burst: 10,
+            per_minute: 30,
+        }
+    }
+}
+
+/// What to do with a connection once it goes over its outbound quota.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaAction {
+    /// Send a policy-violation close frame and drop the connection.
+    #[default]
+    Disconnect,
+    /// Keep the connection but stop sending it text and binary frames.
+    Drop,
+}
+
+/// Limits on the total traffic sent to one connection over its lifetime.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct OutboundQuota {
+    pub max_messages: Option<u64>,
+    pub max_bytes: Option<u64>,
+    pub action: QuotaAction,
+}
+
+impl OutboundQuota {
+    /// Whether a connection that has been sent `messages` frames totalling `bytes` is over quota.
+    ///
+    /// # Example
+    /// ```
+    /// let quota = config::OutboundQuota {
+    ///     max_bytes: Some(1024),
+    ///     ..Default::default()
+    /// };
+    /// assert!(!quota.is_exceeded(10, 1024));
+    /// assert!(quota.is_exceeded(11, 1025));
+    /// ```
+    pub fn is_exceeded(&self, messages: u64, bytes: u64) -> bool {
+        self.max_messages.is_some_and(|max| messages > max)
+            || self.max_bytes.is_some_and(|max| bytes > max)
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             network: InterfaceConfig::default(),
+            limits: LimitsConfig::default(),
+            branding: BrandingConfig::default(),
+            logging: LoggingConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
             database_path: "database.db".to_string(),
+            database_url: None,
+            read_database_url: None,
+            database_busy_timeout_ms: 5000,
+            database_pool_size: 4,
+            shutdown_grace_secs: 5,
+            disconnect_jitter_ms: 2000,
+            json_pretty: true,
+            static_dir: None,
+            tls: None,
+            prioritize_critical_messages: true,
+            task_max_restarts: 5,
+            task_restart_backoff_ms: 1000,
+            snapshot_path: "snapshot.json".to_string(),
+            admin_token: None,
+        }
+    }
+}
+
+/// The storage backend a config resolves to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DatabaseBackend {
+    Sqlite(PathBuf),
+}
+
+/// Why a `database_url` couldn't be turned into a backend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DatabaseUrlError {
+    /// The URL has no `scheme://` prefix.
+    MissingScheme(String),
+    /// The scheme is recognized but that backend isn't part of this build.
+    BackendNotCompiled(String),
+    /// Nobody has heard of this scheme.
+    UnknownScheme(String),
+    /// The URL names a backend but no database.
+    EmptyLocation(String),
+}
+
+impl fmt::Display for DatabaseUrlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingScheme(url) => {
+                write!(
+                    f,
+                    "database_url '{}' is missing a scheme like sqlite://",
+                    url
+                )
+            }
+            Self::BackendNotCompiled(scheme) => {
+                write!(
+                    f,
+                    "the '{}' database backend is not compiled into this build",
+                    scheme
+                )
+            }
+            Self::UnknownScheme(scheme) => write!(f, "unknown database_url scheme '{}'", scheme),
+            Self::EmptyLocation(url) => {
+                write!(f, "database_url '{}' does not name a database", url)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DatabaseUrlError {}
+
+impl DatabaseBackend {
+    /// Parse a database URL into the backend it selects.
+    ///
+    /// # Example
+    /// ```
+    /// use config::DatabaseBackend;
+    /// use std::path::PathBuf;
+    ///
+    /// assert_eq!(
+    ///     DatabaseBackend::from_url("sqlite://canvas.db"),
+    ///     Ok(DatabaseBackend::Sqlite(PathBuf::from("canvas.db")))
+    /// );
+    /// ```
+    pub fn from_url(url: &str) -> Result<Self, DatabaseUrlError> {
+        let (scheme, location) = url
+            .split_once("://")
+            .ok_or_else(|| DatabaseUrlError::MissingScheme(url.to_string()))?;
+        match scheme.to_ascii_lowercase().as_str() {
+            "sqlite" => {
+                if location.is_empty() {
+                    Err(DatabaseUrlError::EmptyLocation(url.to_string()))
+                } else {
+                    Ok(Self::Sqlite(PathBuf::from(location)))
+                }
+            }
+            "postgres" | "postgresql" => {
+                Err(DatabaseUrlError::BackendNotCompiled(scheme.to_string()))
+            }
+            _ => Err(DatabaseUrlError::UnknownScheme(scheme.to_string())),
+        }
+    }
+}
+
+impl Config {
+    /// The backend to use: `database_url` if set, otherwise SQLite at `database_path`.
+    pub fn database_backend(&self) -> Result<DatabaseBackend, DatabaseUrlError> {
+        match &self.database_url {
+            Some(url) => DatabaseBackend::from_url(url),
+            None => Ok(DatabaseBackend::Sqlite(PathBuf::from(&self.database_path))),
         }
     }
+
+    /// The backend reads go to, if `read_database_url` is set.
+    pub fn read_database_backend(&self) -> Result<Option<DatabaseBackend>, DatabaseUrlError> {
+        self.read_database_url
+            .as_deref()
+            .map(DatabaseBackend::from_url)
+            .transpose()
+    }
+}
+
+/// Serialize `value` to JSON, either pretty-printed or compact.
+///
+/// Everything that emits JSON goes through here so the choice is made in one place.
+///
+/// # Example
+/// ```
+/// let config = config::Config::default();
+/// let pretty = config::serialize_json(&config, true).unwrap();
+/// let compact = config::serialize_json(&config, false).unwrap();
+/// assert!(pretty.len() > compact.len());
+///
+/// let a: serde_json::Value = serde_json::from_str(&pretty).unwrap();
+/// let b: serde_json::Value = serde_json::from_str(&compact).unwrap();
+/// assert_eq!(a, b);
+/// ```
+pub fn serialize_json<T: Serialize>(value: &T, pretty: bool) -> serde_json::Result<String> {
+    if pretty {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    }
 }
 
 pub fn load_config(path: &str) -> Config {
+    load_config_with(path, false)
+}
+
+/// Load the config at `path`, like [`load_config`].
+///
+/// `path` is either a base name, tried as `.json` and then `.toml`, or a file ending in one
+/// of those, which is read in that format and nothing else.
+///
+/// With `dry_run` set, a missing config file is not created: nothing is prompted for or
+/// written, the would-be file is logged, and the defaults are returned.
+///
+/// # Example
+/// ```
+/// let base = std::env::temp_dir().join("rustcanvas-dry-run-doctest/config");
+/// let base = base.to_str().unwrap();
+///
+/// let config = config::load_config_with(base, true);
+/// assert_eq!(config.network.port, config::Config::default().network.port);
+/// assert!(!std::path::Path::new(&format!("{}.json", base)).exists());
+/// assert!(!std::path::Path::new(&format!("{}.toml", base)).exists());
+/// ```
+pub fn load_config_with(path: &str, dry_run: bool) -> Config {
+    if let Some(warning) = select_config_file(path).conflict_warning() {
+        tracing::warn!("{}", warning);
+    }
     match find_config_type(path) {
         ConfigTypes::Json => {
-            let file_path = format!("{}.json", path);
+            let file_path = config_file_path(path, ConfigTypes::Json);
             let file_content = fs::read_to_string(&file_path).expect("Failed to read config file");
             serde_json::from_str(&file_content).expect("Failed to parse config file")
         }
         ConfigTypes::Toml => {
-            let file_path = format!("{}.toml", path);
+            let file_path = config_file_path(path, ConfigTypes::Toml);
             let file_content = fs::read_to_string(&file_path).expect("Failed to read config file");
             toml::from_str(&file_content).expect("Failed to parse config file")
         }
+        ConfigTypes::None if dry_run && explicit_format(path).is_some() => {
+            tracing::info!(
+                "Dry run: no config file found, would write defaults to {}",
+                path
+            );
+            Config::default()
+        }
+        ConfigTypes::None if dry_run => {
+            tracing::info!(
+                "Dry run: no config file found, would prompt for a format and write defaults to {0}.json or {0}.toml",
+                path
+            );
+            Config::default()
+        }
+        // The format was already picked by naming the file, nothing to ask
+        ConfigTypes::None if explicit_format(path).is_some() => create_default_config(path, 'j'),
         ConfigTypes::None => {
-            let default_config = Config::default();
-            let file_path = format!("{}.json", path);
-            let dir = Path::new(&file_path).parent().unwrap();
-            fs::create_dir_all(dir).expect("Failed to create directory structure");
-            let choice = utils::input::choice(
+            // Nobody may be there to answer (CI, a service manager), so fall back to JSON
+            let choice = utils::input::choice_with_timeout(
                 "jt",
                 false,
                 Some("No config file found, create a new one? [j]son/[t]oml: "),
+                FORMAT_PROMPT_TIMEOUT,
+                'j',
             );
-            match choice {
-                'j' | 'J' => {
-                    let json_content = serde_json::to_string_pretty(&default_config)
-                        .expect("Failed to serialize default config to JSON");
-                    fs::write(&file_path, json_content)
-                        .expect("Failed to write default config file");
-                    default_config
-                }
-                't' | 'T' => {
-                    let toml_file_path = format!("{}.toml", path);
-                    let toml_content = toml::to_string_pretty(&default_config)
-                        .expect("Failed to serialize default config to TOML");
-                    fs::write(&toml_file_path, toml_content)
-                        .expect("Failed to write default config file");
-                    default_config
-                }
-                _ => panic!("How did you get here?"),
+            create_default_config(path, choice)
+        }
+    }
+}
+
+/// Write the default config for `path` (without extension) in the format picked by `answer`,
+/// and return it. This is what [`load_config`] does when no config file exists yet.
+///
+/// `t` or `T` writes TOML. Any other answer writes JSON - whatever the prompt hands back, the
+/// server still gets a config file rather than a panic. A `path` that ends in `.json` or
+/// `.toml` is written as is, in that format, whatever the answer.
+///
+/// # Example
+/// ```
+/// let base = std::env::temp_dir().join("rustcanvas-default-config-doctest/config");
+/// let config = config::create_default_config(base.to_str().unwrap(), 'j');
+/// ```
+pub fn create_default_config(path: &str, answer: char) -> Config {
+    let default_config = Config::default();
+    if let Some(dir) = Path::new(path).parent() {
+        fs::create_dir_all(dir).expect("Failed to create directory structure");
+    }
+    let answer = match explicit_format(path) {
+        Some(ConfigTypes::Toml) => 't',
+        Some(_) => 'j',
+        None => answer,
+    };
+    match answer {
+        't' | 'T' => {
+            let toml_file_path = config_file_path(path, ConfigTypes::Toml);
+            let toml_content = toml::to_string_pretty(&default_config)
+                .expect("Failed to serialize default config to TOML");
+            fs::write(&toml_file_path, toml_content).expect("Failed to write default config file");
+        }
+        other => {
+            if !matches!(other, 'j' | 'J') {
+                tracing::warn!("Unexpected answer {:?}, writing a JSON config", other);
             }
+            let file_path = config_file_path(path, ConfigTypes::Json);
+            let json_content = serialize_json(&default_config, default_config.json_pretty)
+                .expect("Failed to serialize default config to JSON");
+            fs::write(&file_path, json_content).expect("Failed to write default config file");
         }
     }
+    default_config
 }
 
 pub fn save_config(path: &str, config: &Config) {
     match find_config_type(path) {
         ConfigTypes::Json => {
-            let file_path = format!("{}.json", path);
-            let json_content =
-                serde_json::to_string_pretty(config).expect("Failed to serialize config to JSON");
+            let file_path = config_file_path(path, ConfigTypes::Json);
+            let json_content = serialize_json(config, config.json_pretty)
+                .expect("Failed to serialize config to JSON");
             fs::write(&file_path, json_content).expect("Failed to write config file");
         }
         ConfigTypes::Toml => {
-            let file_path = format!("{}.toml", path);
+            let file_path = config_file_path(path, ConfigTypes::Toml);
             let toml_content =
                 toml::to_string_pretty(config).expect("Failed to serialize config to TOML");
             fs::write(&file_path, toml_content).expect("Failed to write config file");
@@ -113,3 +633,238 @@ pub fn save_config(path: &str, config: &Config) {
         ConfigTypes::None => panic!("No configuration type found"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempConfigDir;
+
+    const JSON_PORT: &str = r#"{ "network": { "port": 4321 } }"#;
+    const TOML_PORT: &str = "[network]\nport = 1234\n";
+
+    #[test]
+    fn base_name_prefers_json() {
+        let dir = TempConfigDir::new("base-name");
+        dir.write("config.json", JSON_PORT);
+        dir.write("config.toml", TOML_PORT);
+        let base = dir.file("config");
+
+        assert_eq!(load_config(&base).network.port, 4321);
+        let selection = select_config_file(&base);
+        assert_eq!(selection.ignored, [dir.path().join("config.toml")]);
+    }
+
+    #[test]
+    fn explicit_file_is_used_even_next_to_another_format() {
+        let dir = TempConfigDir::new("explicit");
+        dir.write("config.json", JSON_PORT);
+        let toml = dir.write("config.toml", TOML_PORT);
+
+        assert_eq!(load_config(&toml).network.port, 1234);
+        assert_eq!(try_load_config(&toml).unwrap().network.port, 1234);
+        let selection = select_config_file(&toml);
+        assert_eq!(selection.chosen, Some(dir.path().join("config.toml")));
+        assert!(selection.ignored.is_empty());
+        assert_eq!(selection.conflict_warning(), None);
+    }
+
+    #[test]
+    fn missing_explicit_file_is_created_in_its_format() {
+        let dir = TempConfigDir::new("explicit-missing");
+        let toml = dir.file("server.toml");
+
+        // No prompt: naming the file already picked the format
+        let config = load_config(&toml);
+        let written: Config = toml::from_str(&fs::read_to_string(&toml).unwrap()).unwrap();
+        assert_eq!(written.network.port, config.network.port);
+        assert!(!dir.path().join("server.toml.json").exists());
+        assert!(!dir.path().join("server.json").exists());
+    }
+
+    #[test]
+    fn save_config_writes_the_explicit_file() {
+        let dir = TempConfigDir::new("explicit-save");
+        dir.write("config.json", JSON_PORT);
+        let toml = dir.write("config.toml", TOML_PORT);
+
+        let mut config = load_config(&toml);
+        config.network.port = 2222;
+        save_config(&toml, &config);
+        assert_eq!(load_config(&toml).network.port, 2222);
+        assert_eq!(load_config(&dir.file("config.json")).network.port, 4321);
+    }
+
+    #[test]
+    fn admin_token_comes_from_the_environment_and_must_not_be_empty() {
+        let mut config = Config::default();
+        config
+            .apply_overrides(|var| (var == ENV_ADMIN_TOKEN).then(|| "s3cret".to_string()))
+            .unwrap();
+        assert_eq!(config.admin_token.as_deref(), Some("s3cret"));
+        assert!(config.validate().is_ok());
+
+        config.admin_token = Some("  ".to_string());
+        assert_eq!(
+            config.validate().unwrap_err().issues[0].field,
+            "admin_token"
+        );
+    }
+
+    #[test]
+    fn both_formats_warn_and_json_wins() {
+        let dir = TempConfigDir::new("conflict");
+        dir.write("config.json", JSON_PORT);
+        dir.write("config.toml", TOML_PORT);
+        let base = dir.file("config");
+
+        let selection = select_config_file(&base);
+        assert_eq!(selection.chosen, Some(dir.path().join("config.json")));
+        let warning = selection.conflict_warning().unwrap();
+        assert!(warning.contains("config.json") && warning.contains("config.toml"));
+
+        // With just one file there's nothing to warn about
+        fs::remove_file(dir.file("config.json")).unwrap();
+        let selection = select_config_file(&base);
+        assert_eq!(selection.chosen, Some(dir.path().join("config.toml")));
+        assert_eq!(selection.conflict_warning(), None);
+    }
+
+    #[test]
+    fn database_urls_pick_a_backend() {
+        assert_eq!(
+            DatabaseBackend::from_url("sqlite:///var/lib/rustcanvas/canvas.db"),
+            Ok(DatabaseBackend::Sqlite(PathBuf::from(
+                "/var/lib/rustcanvas/canvas.db"
+            )))
+        );
+        assert!(matches!(
+            DatabaseBackend::from_url("postgres://localhost/canvas"),
+            Err(DatabaseUrlError::BackendNotCompiled(_))
+        ));
+        assert!(matches!(
+            DatabaseBackend::from_url("mongodb://localhost"),
+            Err(DatabaseUrlError::UnknownScheme(_))
+        ));
+        assert!(matches!(
+            DatabaseBackend::from_url("canvas.db"),
+            Err(DatabaseUrlError::MissingScheme(_))
+        ));
+        assert!(matches!(
+            DatabaseBackend::from_url("sqlite://"),
+            Err(DatabaseUrlError::EmptyLocation(_))
+        ));
+    }
+
+    #[test]
+    fn default_config_format_follows_the_answer() {
+        let dir = TempConfigDir::new("default-config");
+        let base = dir.file("nested/config");
+
+        // Not one of the offered answers, so it's JSON
+        let config = create_default_config(&base, '?');
+        assert_eq!(config.network.port, Config::default().network.port);
+        assert!(Path::new(&format!("{}.json", base)).is_file());
+        assert_eq!(load_config(&base).network.port, config.network.port);
+
+        fs::remove_file(format!("{}.json", base)).unwrap();
+        create_default_config(&base, 'T');
+        assert!(Path::new(&format!("{}.toml", base)).is_file());
+    }
+
+    #[test]
+    fn validate_reports_only_errors() {
+        let mut config = Config::default();
+        // Both aliases are fine, as is a database directory that doesn't exist yet
+        let dir = TempConfigDir::new("validate");
+        config.network.interface = "*".to_string();
+        config.database_path = dir.file("not/yet/there/canvas.db");
+        assert_eq!(config.validate(), Ok(()));
+
+        config.network.interface = "localhot".to_string();
+        let err = config.validate().unwrap_err();
+        assert_eq!(err.issues.len(), 1);
+        assert_eq!(
+            err.to_string(),
+            "invalid config: network.interface: 'localhot' is not an IP address, '*' or 'localhost'"
+        );
+
+        // A database under a plain file can never be created
+        config.network.interface = "127.0.0.1".to_string();
+        let file = dir.write("plain-file", "");
+        config.database_path = format!("{}/canvas.db", file);
+        assert_eq!(
+            config.validate().unwrap_err().issues[0].field,
+            "database_path"
+        );
+    }
+
+    #[test]
+    fn validate_all_collects_every_issue() {
+        let mut config = Config::default();
+        config.network.port = 0;
+        config.network.interface = "localhot".to_string();
+        config.database_url = Some("postgres://localhost/canvas".to_string());
+        config.heartbeat.timeout_secs = 10;
+
+        let issues = config.validate_all().unwrap_err();
+        let fields: Vec<_> = issues
+            .iter()
+            .filter(|i| i.is_error())
+            .map(|i| i.field)
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                "network.interface",
+                "network.port",
+                "heartbeat.timeout_secs",
+                "database_url"
+            ]
+        );
+
+        // A timeout equal to the ping interval is still too short
+        let mut config = Config::default();
+        config.heartbeat.timeout_secs = config.heartbeat.ping_interval_secs;
+        let issues = config.validate_all().unwrap_err();
+        assert_eq!(issues[0].field, "heartbeat.timeout_secs");
+        config.heartbeat.timeout_secs += 1;
+        assert!(config.validate_all().is_ok());
+    }
+
+    #[test]
+    fn overrides_apply_in_order_and_leave_the_rest_alone() {
+        let mut config = Config::default();
+        let interface = config.network.interface.clone();
+        config
+            .apply_overrides(|var| match var {
+                ENV_PORT => Some("8080".to_string()),
+                ENV_DATABASE_PATH => Some("/data/canvas.db".to_string()),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(config.network.port, 8080);
+        assert_eq!(config.database_path, "/data/canvas.db");
+        assert_eq!(config.network.interface, interface);
+
+        // A bad value is reported and changes nothing
+        let err = config
+            .apply_overrides(|var| (var == ENV_PORT).then(|| "99999".to_string()))
+            .unwrap_err();
+        assert_eq!(err.var, ENV_PORT);
+        assert_eq!(config.network.port, 8080);
+
+        // The command line beats the environment, and only for what it sets
+        config.network.interface = "0.0.0.0".to_string();
+        config.apply_cli_overrides(&CliOverrides {
+            port: Some(9090),
+            interface: None,
+        });
+        assert_eq!(config.network.port, 9090);
+        assert_eq!(config.network.interface, "0.0.0.0");
+
+        // No arguments, no changes
+        let before = format!("{:?}", config);
+        config.apply_cli_overrides(&CliOverrides::default());
+        assert_eq!(format!("{:?}", config), before);
+    }
+}
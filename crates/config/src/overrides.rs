@@ -0,0 +1,117 @@
+//! Environment variable and command line overrides, applied on top of whatever the config
+//! file says.
+
+use crate::Config;
+use std::fmt;
+
+/// Port to listen on, e.g. `RUSTCANVAS_PORT=8080`.
+pub const ENV_PORT: &str = "RUSTCANVAS_PORT";
+/// Interface to bind, e.g. `RUSTCANVAS_INTERFACE=127.0.0.1`.
+pub const ENV_INTERFACE: &str = "RUSTCANVAS_INTERFACE";
+/// SQLite database file, e.g. `RUSTCANVAS_DATABASE_PATH=/data/canvas.db`.
+pub const ENV_DATABASE_PATH: &str = "RUSTCANVAS_DATABASE_PATH";
+/// Token for the admin endpoints, e.g. `RUSTCANVAS_ADMIN_TOKEN=$(openssl rand -hex 32)`.
+pub const ENV_ADMIN_TOKEN: &str = "RUSTCANVAS_ADMIN_TOKEN";
+
+/// An override variable was set to something unusable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvOverrideError {
+    pub var: &'static str,
+    pub value: String,
+    pub reason: String,
+}
+
+impl fmt::Display for EnvOverrideError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}='{}': {}", self.var, self.value, self.reason)
+    }
+}
+
+impl std::error::Error for EnvOverrideError {}
+
+/// Settings given on the command line. `None` leaves the setting alone.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CliOverrides {
+    pub port: Option<u16>,
+    pub interface: Option<String>,
+}
+
+impl Config {
+    /// Apply command line settings on top of this config.
+    ///
+    /// Meant to run after [`apply_env_overrides`](Self::apply_env_overrides), so precedence
+    /// ends up command line > env > file > defaults.
+    ///
+    /// # Example
+    /// ```
+    /// use config::{CliOverrides, Config};
+    ///
+    /// let mut config = Config::default();
+    /// config.apply_cli_overrides(&CliOverrides {
+    ///     port: Some(9090),
+    ///     interface: None,
+    /// });
+    /// assert_eq!(config.network.port, 9090);
+    /// ```
+    pub fn apply_cli_overrides(&mut self, overrides: &CliOverrides) {
+        if let Some(port) = overrides.port {
+            self.network.port = port;
+        }
+        if let Some(interface) = &overrides.interface {
+            // Checked by validate_all like any other source
+            self.network.interface = interface.clone();
+        }
+    }
+
+    /// Apply the `RUSTCANVAS_*` environment variables on top of this config.
+    ///
+    /// Precedence ends up env > file > defaults. Unset variables leave the current value alone.
+    pub fn apply_env_overrides(&mut self) -> Result<(), EnvOverrideError> {
+        self.apply_overrides(|var| std::env::var(var).ok())
+    }
+
+    /// Like [`apply_env_overrides`](Self::apply_env_overrides), but reads variables through
+    /// `lookup` instead of the process environment.
+    ///
+    /// # Example
+    /// ```
+    /// use config::{Config, ENV_PORT};
+    ///
+    /// let mut config = Config::default();
+    /// config
+    ///     .apply_overrides(|var| (var == ENV_PORT).then(|| "8080".to_string()))
+    ///     .unwrap();
+    /// assert_eq!(config.network.port, 8080);
+    /// ```
+    pub fn apply_overrides(
+        &mut self,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Result<(), EnvOverrideError> {
+        if let Some(value) = lookup(ENV_PORT) {
+            self.network.port = value.trim().parse().map_err(|_| EnvOverrideError {
+                var: ENV_PORT,
+                reason: "expected a port number between 0 and 65535".to_string(),
+                value,
+            })?;
+        }
+        if let Some(value) = lookup(ENV_INTERFACE) {
+            // Whether it's a usable address is validate_all's job, same as for the file
+            self.network.interface = value;
+        }
+        if let Some(value) = lookup(ENV_DATABASE_PATH) {
+            if value.is_empty() {
+                return Err(EnvOverrideError {
+                    var: ENV_DATABASE_PATH,
+                    value,
+                    reason: "path is empty".to_string(),
+                });
+            }
+            self.database_path = value;
+        }
+        if let Some(value) = lookup(ENV_ADMIN_TOKEN) {
+            // An empty token is reported by validate_all rather than silently disabling admin
+            self.admin_token = Some(value);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+//! Startup validation that reports every config problem at once.
+
+use crate::{Config, DatabaseBackend};
+use std::{fmt, net::IpAddr, path::Path};
+
+/// How bad a config problem is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueSeverity {
+    /// The server can't run like this.
+    Error,
+    /// Probably a mistake, but the server can still start.
+    Warning,
+}
+
+/// A single problem found while validating a [`Config`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    pub severity: IssueSeverity,
+    /// Dotted path of the offending setting, e.g. `network.port`.
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    fn error(field: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            severity: IssueSeverity::Error,
+            field,
+            message: message.into(),
+        }
+    }
+
+    fn warning(field: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            severity: IssueSeverity::Warning,
+            field,
+            message: message.into(),
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == IssueSeverity::Error
+    }
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Interface names accepted besides IP addresses: every address, and loopback.
+pub const INTERFACE_ALIASES: [&str; 2] = ["*", "localhost"];
+
+/// The errors that stop a [`Config`] from being used, as returned by [`Config::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigValidationError {
+    /// Only issues with [`IssueSeverity::Error`], in the order they were found.
+    pub issues: Vec<ConfigIssue>,
+}
+
+impl fmt::Display for ConfigValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid config")?;
+        for (i, issue) in self.issues.iter().enumerate() {
+            let sep = if i == 0 { ": " } else { "; " };
+            write!(f, "{}{}", sep, issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigValidationError {}
+
+// A missing directory is fine as long as it can be made under the nearest one that exists
+fn check_creatable(dir: &Path) -> Result<(), String> {
+    let mut existing = dir;
+    while !existing.exists() {
+        match existing.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => existing = parent,
+            // Relative to the working directory, which exists by definition
+            _ => return Ok(()),
+        }
+    }
+    if !existing.is_dir() {
+        return Err(format!(
+            "can't use directory '{}', '{}' is not a directory",
+            dir.display(),
+            existing.display()
+        ));
+    }
+    if existing
+        .metadata()
+        .is_ok_and(|meta| meta.permissions().readonly())
+    {
+        return Err(format!(
+            "can't use directory '{}', '{}' is read-only",
+            dir.display(),
+            existing.display()
+        ));
+    }
+    Ok(())
+}
+
+impl Config {
+    /// Check the settings the server can't start without, failing with every one that's wrong.
+    ///
+    /// This is [`validate_all`](Self::validate_all) without the warnings, as a single error
+    /// whose message names each bad setting and why. Run it on the final config, after the
+    /// env and command line overrides, since those can fix what the file got wrong.
+    ///
+    /// # Example
+    /// ```
+    /// let mut config = config::Config::default();
+    /// assert_eq!(config.validate(), Ok(()));
+    ///
+    /// config.network.port = 0;
+    /// assert_eq!(config.validate().unwrap_err().issues[0].field, "network.port");
+    /// ```
+    pub fn validate(&self) -> Result<(), ConfigValidationError> {
+        let issues: Vec<ConfigIssue> = match self.validate_all() {
+            Ok(()) => return Ok(()),
+            Err(issues) => issues.into_iter().filter(ConfigIssue::is_error).collect(),
+        };
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigValidationError { issues })
+        }
+    }
+
+    /// Check every setting and collect all the problems instead of stopping at the first.
+    ///
+    /// Returns `Err` with every issue found, errors and warnings alike; use
+    /// [`ConfigIssue::is_error`] to decide whether to abort.
+    ///
+    /// # Example
+    /// ```
+    /// let mut config = config::Config::default();
+    /// config.heartbeat.timeout_secs = 10;
+    ///
+    /// for issue in config.validate_all().unwrap_err() {
+    ///     println!("{}: {}", issue.field, issue.message);
+    /// }
+    /// ```
+    pub fn validate_all(&self) -> Result<(), Vec<ConfigIssue>> {
+        let mut issues = Vec::new();
+
+        let interface = self.network.interface.as_str();
+        if !INTERFACE_ALIASES.contains(&interface) && interface.parse::<IpAddr>().is_err() {
+            issues.push(ConfigIssue::error(
+                "network.interface",
+                format!("'{}' is not an IP address, '*' or 'localhost'", interface),
+            ));
+        }
+
+        match self.network.port {
+            0 => issues.push(ConfigIssue::error(
+                "network.port",
+                "port 0 would bind a random port",
+            )),
+            port if port < 1024 => issues.push(ConfigIssue::warning(
+                "network.port",
+                format!(
+                    "port {} is privileged and may need elevated permissions",
+                    port
+                ),
+            )),
+            _ => {}
+        }
+
+        let heartbeat = &self.heartbeat;
+        if heartbeat.ping_interval_secs == 0 {
+            issues.push(ConfigIssue::error(
+                "heartbeat.ping_interval_secs",
+                "must be at least 1 second",
+            ));
+        }
+        // Equal isn't enough either: the pong lands a little after the next ping is due
+        if heartbeat.timeout_secs <= heartbeat.ping_interval_secs {
+            issues.push(ConfigIssue::error(
+                "heartbeat.timeout_secs",
+                format!(
+                    "{}s is not longer than the {}s ping interval, every client would time out",
+                    heartbeat.timeout_secs, heartbeat.ping_interval_secs
+                ),
+            ));
+        }
+
+        if self.limits.max_message_bytes == 0 {
+            issues.push(ConfigIssue::error(
+                "limits.max_message_bytes",
+                "must allow at least 1 byte",
+            ));
+        }
+        if let Some(rate) = &self.limits.connect_rate {
+            if rate.burst == 0 {
+                issues.push(ConfigIssue::error(
+                    "limits.connect_rate.burst",
+                    "must allow at least 1 connection",
+                ));
+            }
+            if rate.per_minute == 0 {
+                issues.push(ConfigIssue::error(
+                    "limits.connect_rate.per_minute",
+                    "must be at least 1, or leave connect_rate unset for no limit",
+                ));
+            }
+        }
+
+        if self.database_pool_size == 0 {
+            issues.push(ConfigIssue::error(
+                "database_pool_size",
+                "must allow at least 1 connection",
+            ));
+        }
+
+        if let Some(tls) = &self.tls {
+            for (field, path) in [
+                ("tls.cert_path", &tls.cert_path),
+                ("tls.key_path", &tls.key_path),
+            ] {
+                if !Path::new(path).is_file() {
+                    issues.push(ConfigIssue::error(
+                        field,
+                        format!("'{}' is not a file", path),
+                    ));
+                }
+            }
+        }
+
+        match self.database_backend() {
+            Ok(DatabaseBackend::Sqlite(path)) => {
+                let field = if self.database_url.is_some() {
+                    "database_url"
+                } else {
+                    "database_path"
+                };
+                if let Some(parent) = path.parent()
+                    && !parent.as_os_str().is_empty()
+                    && let Err(message) = check_creatable(parent)
+                {
+                    issues.push(ConfigIssue::error(field, message));
+                }
+                if self.database_url.is_some()
+                    && self.database_path != Config::default().database_path
+                {
+                    issues.push(ConfigIssue::warning(
+                        "database_path",
+                        "ignored because database_url is set",
+                    ));
+                }
+            }
+            Err(e) => issues.push(ConfigIssue::error("database_url", e.to_string())),
+        }
+        if let Err(e) = self.read_database_backend() {
+            issues.push(ConfigIssue::error("read_database_url", e.to_string()));
+        }
+
+        if self
+            .admin_token
+            .as_deref()
+            .is_some_and(|token| token.trim().is_empty())
+        {
+            issues.push(ConfigIssue::error(
+                "admin_token",
+                "is empty, leave it unset to disable the admin endpoints",
+            ));
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(issues)
+        }
+    }
+}
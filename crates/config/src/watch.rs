@@ -0,0 +1,218 @@
+//! Reloading the config file while the server runs.
+
+use crate::{CliOverrides, Config, ConfigTypes, config_base, config_file_path, find_config_type};
+use notify::{RecursiveMode, Watcher};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fmt, fs};
+use tokio::sync::{Mutex, mpsc};
+
+// Editors tend to write a file in several steps, so wait for them to settle
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Why a config file couldn't be (re)loaded.
+#[derive(Debug)]
+pub enum ConfigLoadError {
+    /// Neither `<path>.json` nor `<path>.toml` exists.
+    NotFound,
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Toml(toml::de::Error),
+}
+
+impl fmt::Display for ConfigLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigLoadError::NotFound => write!(f, "no config file found"),
+            ConfigLoadError::Io(e) => write!(f, "failed to read config file: {}", e),
+            ConfigLoadError::Json(e) => write!(f, "failed to parse config file: {}", e),
+            ConfigLoadError::Toml(e) => write!(f, "failed to parse config file: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigLoadError {}
+
+/// Read the config at `path`, returning errors instead of panicking.
+///
+/// `path` works as in [`load_config_with`](crate::load_config_with).
+///
+/// Unlike [`load_config`](crate::load_config) this never creates a missing file.
+pub fn try_load_config(path: &str) -> Result<Config, ConfigLoadError> {
+    match find_config_type(path) {
+        ConfigTypes::Json => {
+            let content = fs::read_to_string(config_file_path(path, ConfigTypes::Json))
+                .map_err(ConfigLoadError::Io)?;
+            serde_json::from_str(&content).map_err(ConfigLoadError::Json)
+        }
+        ConfigTypes::Toml => {
+            let content = fs::read_to_string(config_file_path(path, ConfigTypes::Toml))
+                .map_err(ConfigLoadError::Io)?;
+            toml::from_str(&content).map_err(ConfigLoadError::Toml)
+        }
+        ConfigTypes::None => Err(ConfigLoadError::NotFound),
+    }
+}
+
+impl Config {
+    /// Take the settings from `new` that can change while running, and keep the rest.
+    ///
+    /// Returns the settings that differ but only take effect after a restart; those keep
+    /// their current value so the config always describes what's actually running.
+    ///
+    /// # Example
+    /// ```
+    /// let mut running = config::Config::default();
+    /// let mut edited = running.clone();
+    /// edited.network.port = 8080;
+    /// edited.heartbeat.ping_interval_secs = 10;
+    ///
+    /// assert_eq!(running.apply_reload(edited), ["network.port"]);
+    /// assert_eq!(running.heartbeat.ping_interval_secs, 10);
+    /// assert_eq!(running.network.port, config::Config::default().network.port);
+    /// ```
+    pub fn apply_reload(&mut self, mut new: Config) -> Vec<&'static str> {
+        let mut requires_restart = Vec::new();
+        macro_rules! keep_running {
+            ($($field:ident).+, $name:literal) => {
+                if new.$($field).+ != self.$($field).+ {
+                    requires_restart.push($name);
+                    new.$($field).+ = self.$($field).+.clone();
+                }
+            };
+        }
+        // Bound once at startup: the listen socket, its certificate and the database connection
+        keep_running!(network.interface, "network.interface");
+        keep_running!(network.port, "network.port");
+        keep_running!(database_path, "database_path");
+        keep_running!(database_url, "database_url");
+        keep_running!(read_database_url, "read_database_url");
+        keep_running!(database_busy_timeout_ms, "database_busy_timeout_ms");
+        keep_running!(database_pool_size, "database_pool_size");
+        keep_running!(tls, "tls");
+        // The supervisor reads these once when it spawns the tasks
+        keep_running!(task_max_restarts, "task_max_restarts");
+        keep_running!(task_restart_backoff_ms, "task_restart_backoff_ms");
+
+        *self = new;
+        requires_restart
+    }
+}
+
+/// Watch the config file at `path` and reload `shared` when it changes.
+///
+/// Each reload goes through the same env overrides, `cli` overrides and validation as startup,
+/// so settings given on the command line keep winning over the file. A file that
+/// fails to parse or validate is logged and ignored, leaving the last good config in place.
+/// Runs until the watcher can't be set up.
+pub async fn watch_config(path: String, shared: Arc<Mutex<Config>>, cli: CliOverrides) {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = match notify::recommended_watcher(move |event| {
+        let _ = tx.send(event);
+    }) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            tracing::warn!("Config hot-reload disabled, can't create a watcher: {}", e);
+            return;
+        }
+    };
+
+    // Watch the directory, not the file - editors often save by replacing the file
+    let base = Path::new(config_base(&path));
+    let dir = match base.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => Path::new(".").to_path_buf(),
+    };
+    if let Err(e) = watcher.watch(&dir, RecursiveMode::NonRecursive) {
+        tracing::warn!(
+            "Config hot-reload disabled, can't watch {}: {}",
+            dir.display(),
+            e
+        );
+        return;
+    }
+    let stem = base.file_name().map(|name| name.to_os_string());
+
+    while let Some(event) = rx.recv().await {
+        let Ok(event) = event else {
+            continue;
+        };
+        let touches_config = event
+            .paths
+            .iter()
+            .any(|changed| changed.file_stem().map(|s| s.to_os_string()) == stem);
+        if !touches_config || event.kind.is_access() {
+            continue;
+        }
+
+        tokio::time::sleep(RELOAD_DEBOUNCE).await;
+        while rx.try_recv().is_ok() {}
+        reload(&path, &shared, &cli).await;
+    }
+}
+
+// Returns the changes that need a restart, or None if the reload was skipped
+async fn reload(
+    path: &str,
+    shared: &Mutex<Config>,
+    cli: &CliOverrides,
+) -> Option<Vec<&'static str>> {
+    let mut new = match try_load_config(path) {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::warn!("Config reload skipped, keeping the previous config: {}", e);
+            return None;
+        }
+    };
+    if let Err(e) = new.apply_env_overrides() {
+        tracing::warn!("Config reload skipped, keeping the previous config: {}", e);
+        return None;
+    }
+    new.apply_cli_overrides(cli);
+    if let Err(issues) = new.validate_all()
+        && issues.iter().any(|issue| issue.is_error())
+    {
+        for issue in issues.iter().filter(|issue| issue.is_error()) {
+            tracing::warn!("Config reload skipped: {}", issue);
+        }
+        return None;
+    }
+
+    let requires_restart = shared.lock().await.apply_reload(new);
+    for field in &requires_restart {
+        tracing::warn!("Config change to {} requires restart", field);
+    }
+    tracing::info!("Config reloaded");
+    Some(requires_restart)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempConfigDir;
+
+    #[tokio::test]
+    async fn reload_keeps_command_line_overrides() {
+        let dir = TempConfigDir::new("reload-cli");
+        let path = dir.write("config.json", r#"{ "network": { "port": 4000 } }"#);
+        let cli = CliOverrides {
+            port: Some(5000),
+            interface: Some("::1".to_string()),
+        };
+        let mut running = try_load_config(&path).unwrap();
+        running.apply_cli_overrides(&cli);
+        let shared = Mutex::new(running);
+
+        // Same file again: the overrides still apply, so nothing looks like a restart change
+        let requires_restart = reload(&path, &shared, &cli).await.unwrap();
+        assert!(requires_restart.is_empty(), "{:?}", requires_restart);
+        assert_eq!(shared.lock().await.network.port, 5000);
+
+        // Without them the file's port differs from the running one
+        let requires_restart = reload(&path, &shared, &CliOverrides::default())
+            .await
+            .unwrap();
+        assert_eq!(requires_restart, ["network.interface", "network.port"]);
+    }
+}
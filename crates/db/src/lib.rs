@@ -1,8 +1,135 @@
 #[allow(dead_code)]
 use std::error::Error;
-use std::path::Path;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long schema initialization waits on a locked database by default.
+pub const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many SQLite connections the pool keeps open by default.
+pub const DEFAULT_POOL_SIZE: u32 = 4;
+
+type Pool = r2d2::Pool<r2d2_sqlite::SqliteConnectionManager>;
+type PooledConnection = r2d2::PooledConnection<r2d2_sqlite::SqliteConnectionManager>;
+
+/// Errors from the database layer.
+#[derive(Debug)]
+pub enum DbError {
+    /// Another process held a lock on the database for longer than the busy timeout.
+    Locked { path: PathBuf, waited: Duration },
+    /// A user with this username already exists.
+    UsernameTaken(String),
+    /// The object can't be stored as-is, e.g. a NaN argument that JSON can't represent.
+    InvalidObject(String),
+    /// A stored JSON column couldn't be encoded or decoded.
+    Serialization(serde_json::Error),
+    /// No connection could be checked out of the pool, or the blocking task was cancelled.
+    Unavailable(String),
+    /// Anything else SQLite complained about.
+    Sqlite(rusqlite::Error),
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DbError::Locked { path, waited } => write!(
+                f,
+                "database {} is locked by another process (waited {:?}); is another instance or an editor holding it open?",
+                path.display(),
+                waited
+            ),
+            DbError::UsernameTaken(username) => {
+                write!(f, "the username '{}' is already taken", username)
+            }
+            DbError::InvalidObject(reason) => write!(f, "invalid object: {}", reason),
+            DbError::Serialization(e) => write!(f, "stored object data is malformed: {}", e),
+            DbError::Unavailable(reason) => write!(f, "database unavailable: {}", reason),
+            DbError::Sqlite(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl Error for DbError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DbError::Sqlite(e) => Some(e),
+            DbError::Serialization(e) => Some(e),
+            DbError::Locked { .. }
+            | DbError::UsernameTaken(_)
+            | DbError::InvalidObject(_)
+            | DbError::Unavailable(_) => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for DbError {
+    fn from(e: rusqlite::Error) -> Self {
+        DbError::Sqlite(e)
+    }
+}
+
+impl From<r2d2::Error> for DbError {
+    fn from(e: r2d2::Error) -> Self {
+        DbError::Unavailable(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for DbError {
+    fn from(e: serde_json::Error) -> Self {
+        DbError::Serialization(e)
+    }
+}
+
+/// True when an insert hit a primary key or unique constraint.
+fn is_unique_violation(e: &rusqlite::Error) -> bool {
+    match e {
+        rusqlite::Error::SqliteFailure(err, _) => matches!(
+            err.extended_code,
+            rusqlite::ffi::SQLITE_CONSTRAINT_PRIMARYKEY | rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE
+        ),
+        _ => false,
+    }
+}
+
+/// Brings databases created by older versions up to the current schema.
+fn migrate(conn: &rusqlite::Connection) -> Result<(), DbError> {
+    // Objects weren't tied to a canvas at first
+    let has_canvas_id = conn
+        .prepare("SELECT 1 FROM pragma_table_info('DrawnObjects') WHERE name = 'canvas_id'")?
+        .exists([])?;
+    if !has_canvas_id {
+        conn.execute_batch(
+            "ALTER TABLE DrawnObjects ADD COLUMN canvas_id TEXT NOT NULL DEFAULT ''",
+        )?;
+    }
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS DrawnObjects_canvas_id ON DrawnObjects (canvas_id)",
+    )?;
+    // Duplicated the primary key's own index, costing a second write per insert
+    conn.execute_batch("DROP INDEX IF EXISTS Users_username")?;
+    Ok(())
+}
+
+/// Packs an RGB tuple into the 0xRRGGBB integer stored in `color_args`.
+fn pack_color((r, g, b): (u8, u8, u8)) -> u32 {
+    (r as u32) << 16 | (g as u32) << 8 | b as u32
+}
+
+fn unpack_color(packed: u32) -> (u8, u8, u8) {
+    ((packed >> 16) as u8, (packed >> 8) as u8, packed as u8)
+}
+
+/// True for the errors SQLite gives when someone else holds a lock.
+fn is_lock_error(e: &rusqlite::Error) -> bool {
+    matches!(
+        e.sqlite_error_code(),
+        Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+    )
+}
 
 /// Represents a user in the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct User {
     /// The username of the user.
     pub username: String,
@@ -18,6 +145,7 @@ pub struct User {
     pub lockout_time: i64,
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct DrawnObject {
     //id to tell us what type of object it is
     pub id: u32,
@@ -30,15 +158,535 @@ pub struct DrawnObject {
     //object boolean args
     pub bool_args: Vec<bool>,
 }
-#[allow(dead_code)]
+/// A pool of SQLite connections. Cheap to clone; clones share the pool.
+///
+/// Writes always go to the primary. Reads go to the replica added with
+/// [`with_read_replica`](Self::with_read_replica) when there is one, and to the primary
+/// otherwise.
+#[derive(Clone)]
 pub struct DatabaseConnection {
-    conn: rusqlite::Connection,
+    pool: Pool,
+    read_pool: Option<Pool>,
 }
 impl DatabaseConnection {
-    pub fn new(path: &Path) -> Result<Self, Box<dyn Error>> {
-        let conn = rusqlite::Connection::open(path)?;
+    /// Opens the database at `path` and makes sure the schema exists.
+    pub fn new(path: &Path) -> Result<Self, DbError> {
+        Self::new_with_timeout(path, DEFAULT_BUSY_TIMEOUT)
+    }
+
+    /// Like [`DatabaseConnection::new`], but gives up waiting on a locked database after
+    /// `busy_timeout` instead of the default.
+    ///
+    /// # Example
+    /// ```
+    /// use db::DatabaseConnection;
+    /// use std::path::Path;
+    /// use std::time::Duration;
+    ///
+    /// let db = DatabaseConnection::new_with_timeout(Path::new(":memory:"), Duration::from_secs(1)).unwrap();
+    /// ```
+    pub fn new_with_timeout(path: &Path, busy_timeout: Duration) -> Result<Self, DbError> {
+        Self::open(path, busy_timeout, DEFAULT_POOL_SIZE)
+    }
+
+    /// Opens a pool of up to `pool_size` connections to the database at `path`.
+    ///
+    /// `":memory:"` always gets a single connection that is never recycled, since every
+    /// extra connection would see its own empty in-memory database.
+    pub fn open(path: &Path, busy_timeout: Duration, pool_size: u32) -> Result<Self, DbError> {
+        let in_memory = path == Path::new(":memory:");
+        let manager = if in_memory {
+            r2d2_sqlite::SqliteConnectionManager::memory()
+        } else {
+            r2d2_sqlite::SqliteConnectionManager::file(path)
+        }
+        .with_init(move |conn| conn.busy_timeout(busy_timeout));
+
+        // No ping on checkout - it would just wait out the busy timeout on a locked file
+        let builder = Pool::builder().test_on_check_out(false);
+        let builder = if in_memory {
+            builder.max_size(1).idle_timeout(None).max_lifetime(None)
+        } else {
+            builder.max_size(pool_size.max(1))
+        };
+        let pool = builder.build(manager)?;
+
+        let conn = pool.get()?;
         let sql = include_str!("sql/init.sql");
-        conn.execute_batch(sql)?;
-        Ok(Self { conn })
+        conn.execute_batch(sql).map_err(|e| {
+            if is_lock_error(&e) {
+                DbError::Locked {
+                    path: path.to_path_buf(),
+                    waited: busy_timeout,
+                }
+            } else {
+                DbError::Sqlite(e)
+            }
+        })?;
+        migrate(&conn)?;
+        Ok(Self {
+            pool,
+            read_pool: None,
+        })
+    }
+
+    /// Send reads to up to `pool_size` read-only connections to the database at `path`.
+    ///
+    /// `path` may be the primary's own file, just to stop long reads from queueing behind
+    /// writes for a pooled connection, or a replicated copy. A copy can lag behind the
+    /// primary, so don't read back something you've just written through it. The schema has
+    /// to exist already - nothing is created through a read-only connection.
+    ///
+    /// # Example
+    /// ```
+    /// use db::DatabaseConnection;
+    /// use std::time::Duration;
+    ///
+    /// let path = std::env::temp_dir().join("rustcanvas-replica-doctest.db");
+    /// let db = DatabaseConnection::new(&path)
+    ///     .unwrap()
+    ///     .with_read_replica(&path, Duration::from_secs(1), 2)
+    ///     .unwrap();
+    /// ```
+    pub fn with_read_replica(
+        mut self,
+        path: &Path,
+        busy_timeout: Duration,
+        pool_size: u32,
+    ) -> Result<Self, DbError> {
+        let manager = r2d2_sqlite::SqliteConnectionManager::file(path)
+            .with_flags(
+                rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY
+                    | rusqlite::OpenFlags::SQLITE_OPEN_URI
+                    | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )
+            .with_init(move |conn| conn.busy_timeout(busy_timeout));
+        let pool = Pool::builder()
+            .test_on_check_out(false)
+            .max_size(pool_size.max(1))
+            .build(manager)?;
+        self.read_pool = Some(pool);
+        Ok(self)
+    }
+
+    fn conn(&self) -> Result<PooledConnection, DbError> {
+        Ok(self.pool.get()?)
+    }
+
+    // For queries that never write - may be a replica that lags the primary
+    fn read_conn(&self) -> Result<PooledConnection, DbError> {
+        Ok(self.read_pool.as_ref().unwrap_or(&self.pool).get()?)
+    }
+
+    /// Run blocking database work on tokio's blocking pool so it never stalls the executor.
+    ///
+    /// # Example
+    /// ```
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// use db::DatabaseConnection;
+    /// use std::path::Path;
+    ///
+    /// let db = DatabaseConnection::new(Path::new(":memory:")).unwrap();
+    /// let objects = db.run(|db| db.get_objects("canvas-1")).await.unwrap();
+    /// assert!(objects.is_empty());
+    /// # });
+    /// ```
+    pub async fn run<F, T>(&self, work: F) -> Result<T, DbError>
+    where
+        F: FnOnce(&DatabaseConnection) -> Result<T, DbError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let db = self.clone();
+        match tokio::task::spawn_blocking(move || work(&db)).await {
+            Ok(result) => result,
+            // A panic in the closure should surface as one, not as a database error
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(e) => Err(DbError::Unavailable(e.to_string())),
+        }
+    }
+
+    /// Cheapest possible round trip, for health checks: fails if no connection answers.
+    pub fn ping(&self) -> Result<(), DbError> {
+        self.conn()?.query_row("SELECT 1", [], |_| Ok(()))?;
+        Ok(())
+    }
+
+    /// Inserts a new user, failing with [`DbError::UsernameTaken`] if the name is in use.
+    ///
+    /// # Example
+    /// ```
+    /// use db::{DatabaseConnection, User};
+    /// use std::path::Path;
+    ///
+    /// let db = DatabaseConnection::new(Path::new(":memory:")).unwrap();
+    /// db.create_user(&User {
+    ///     username: "admin".to_string(),
+    ///     password_hash: "hash".to_string(),
+    ///     security_key: None,
+    ///     salt: "salt".to_string(),
+    ///     permissions: u16::MAX,
+    ///     lockout_time: -1,
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn create_user(&self, user: &User) -> Result<(), DbError> {
+        self.conn()?
+            .execute(
+                "INSERT INTO Users (username, password_hash, security_key, salt, permissions, lockout_time)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                rusqlite::params![
+                    user.username,
+                    user.password_hash,
+                    user.security_key,
+                    user.salt,
+                    user.permissions,
+                    user.lockout_time,
+                ],
+            )
+            .map_err(|e| {
+                if is_unique_violation(&e) {
+                    DbError::UsernameTaken(user.username.clone())
+                } else {
+                    DbError::Sqlite(e)
+                }
+            })?;
+        Ok(())
+    }
+    /// Looks a user up by username.
+    ///
+    /// # Example
+    /// ```
+    /// use db::DatabaseConnection;
+    /// use std::path::Path;
+    ///
+    /// let db = DatabaseConnection::new(Path::new(":memory:")).unwrap();
+    /// assert_eq!(db.get_user("alice").unwrap(), None);
+    /// ```
+    pub fn get_user(&self, username: &str) -> Result<Option<User>, DbError> {
+        let conn = self.read_conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT username, password_hash, security_key, salt, permissions, lockout_time
+             FROM Users WHERE username = ?1",
+        )?;
+        let mut rows = stmt.query_map([username], |row| {
+            Ok(User {
+                username: row.get(0)?,
+                password_hash: row.get(1)?,
+                security_key: row.get(2)?,
+                salt: row.get(3)?,
+                permissions: row.get(4)?,
+                lockout_time: row.get(5)?,
+            })
+        })?;
+        Ok(rows.next().transpose()?)
+    }
+
+    /// Overwrites everything but the username. Returns false if there's no such user.
+    pub fn update_user(&self, user: &User) -> Result<bool, DbError> {
+        let updated = self.conn()?.execute(
+            "UPDATE Users
+             SET password_hash = ?2, security_key = ?3, salt = ?4, permissions = ?5, lockout_time = ?6
+             WHERE username = ?1",
+            rusqlite::params![
+                user.username,
+                user.password_hash,
+                user.security_key,
+                user.salt,
+                user.permissions,
+                user.lockout_time,
+            ],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Saves an object on a canvas and returns its row id.
+    ///
+    /// Objects come back from [`get_objects`](Self::get_objects) exactly as they went in.
+    /// Non-finite numbers are rejected since the JSON columns can't hold them.
+    ///
+    /// # Example
+    /// ```
+    /// use db::{DatabaseConnection, DrawnObject};
+    /// use std::path::Path;
+    ///
+    /// let db = DatabaseConnection::new(Path::new(":memory:")).unwrap();
+    /// let dot = DrawnObject { id: 1, num_args: vec![4.0, 2.0], str_args: vec![], color_args: vec![(0, 0, 0)], bool_args: vec![] };
+    /// let row = db.insert_object("canvas-1", &dot).unwrap();
+    /// assert_eq!(db.get_objects("canvas-1").unwrap(), [(row, dot)]);
+    /// ```
+    pub fn insert_object(&self, canvas_id: &str, obj: &DrawnObject) -> Result<i64, DbError> {
+        if let Some(n) = obj.num_args.iter().find(|n| !n.is_finite()) {
+            return Err(DbError::InvalidObject(format!(
+                "numeric argument {} is not finite",
+                n
+            )));
+        }
+        let colors: Vec<u32> = obj.color_args.iter().copied().map(pack_color).collect();
+        let conn = self.conn()?;
+        conn.execute(
+            "INSERT INTO DrawnObjects (canvas_id, type, num_args, str_args, color_args, bool_args)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            rusqlite::params![
+                canvas_id,
+                obj.id,
+                serde_json::to_string(&obj.num_args)?,
+                serde_json::to_string(&obj.str_args)?,
+                serde_json::to_string(&colors)?,
+                serde_json::to_string(&obj.bool_args)?,
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// How many objects are stored across every canvas.
+    pub fn count_objects(&self) -> Result<u64, DbError> {
+        let count =
+            self.read_conn()?
+                .query_row("SELECT COUNT(*) FROM DrawnObjects", [], |row| row.get(0))?;
+        Ok(count)
+    }
+
+    /// Every object on a canvas with its row id, oldest first so they replay in draw order.
+    pub fn get_objects(&self, canvas_id: &str) -> Result<Vec<(i64, DrawnObject)>, DbError> {
+        let conn = self.read_conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, type, num_args, str_args, color_args, bool_args
+             FROM DrawnObjects WHERE canvas_id = ?1 ORDER BY id",
+        )?;
+        let rows = stmt.query_map([canvas_id], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, u32>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, String>(5)?,
+            ))
+        })?;
+
+        let mut objects = Vec::new();
+        for row in rows {
+            let (row_id, kind, num_args, str_args, color_args, bool_args) = row?;
+            let colors: Vec<u32> = serde_json::from_str(&color_args)?;
+            objects.push((
+                row_id,
+                DrawnObject {
+                    id: kind,
+                    num_args: serde_json::from_str(&num_args)?,
+                    str_args: serde_json::from_str(&str_args)?,
+                    color_args: colors.into_iter().map(unpack_color).collect(),
+                    bool_args: serde_json::from_str(&bool_args)?,
+                },
+            ));
+        }
+        Ok(objects)
+    }
+
+    /// Removes an object by row id. Returns false if there was no such object.
+    pub fn delete_object(&self, id: i64) -> Result<bool, DbError> {
+        let removed = self
+            .conn()?
+            .execute("DELETE FROM DrawnObjects WHERE id = ?1", [id])?;
+        Ok(removed > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_db() -> DatabaseConnection {
+        DatabaseConnection::new(Path::new(":memory:")).unwrap()
+    }
+
+    fn object_with(num_args: Vec<f64>) -> DrawnObject {
+        DrawnObject {
+            id: 1,
+            num_args,
+            str_args: Vec::new(),
+            color_args: Vec::new(),
+            bool_args: Vec::new(),
+        }
+    }
+
+    fn stored_bits(db: &DatabaseConnection, num_args: Vec<f64>) -> (Vec<u64>, Vec<u64>) {
+        let expected = num_args.iter().map(|n| n.to_bits()).collect();
+        let row = db.insert_object("canvas", &object_with(num_args)).unwrap();
+        let (_, stored) = db
+            .get_objects("canvas")
+            .unwrap()
+            .into_iter()
+            .find(|(id, _)| *id == row)
+            .unwrap();
+        (
+            stored.num_args.iter().map(|n| n.to_bits()).collect(),
+            expected,
+        )
+    }
+
+    #[test]
+    fn floats_round_trip_bit_for_bit() {
+        let db = memory_db();
+        // Came back as ...cc73 before serde_json's float_roundtrip was enabled
+        let (stored, expected) = stored_bits(&db, vec![1.0715660391465826e-75]);
+        assert_eq!(stored, expected);
+        assert_eq!(stored, [0x305f050c368dcc74]);
+
+        let (stored, expected) = stored_bits(
+            &db,
+            vec![f64::MIN_POSITIVE, f64::MAX, -0.0, 5e-324, 0.1 + 0.2],
+        );
+        assert_eq!(stored, expected);
+    }
+
+    #[test]
+    fn random_floats_round_trip_bit_for_bit() {
+        // splitmix64 over raw bit patterns, so every exponent gets covered
+        let mut seed = 0x9e37_79b9_7f4a_7c15_u64;
+        let mut next = || {
+            seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = seed;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        let sample: Vec<f64> = std::iter::repeat_with(|| f64::from_bits(next()))
+            .filter(|n| n.is_finite())
+            .take(2000)
+            .collect();
+
+        let (stored, expected) = stored_bits(&memory_db(), sample);
+        assert_eq!(stored, expected);
+    }
+
+    #[test]
+    fn non_finite_numbers_are_rejected() {
+        let db = memory_db();
+        for n in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let err = db
+                .insert_object("canvas", &object_with(vec![n]))
+                .unwrap_err();
+            assert!(matches!(err, DbError::InvalidObject(_)));
+        }
+        assert!(db.get_objects("canvas").unwrap().is_empty());
+    }
+
+    fn user(username: &str) -> User {
+        User {
+            username: username.to_string(),
+            password_hash: "hash".to_string(),
+            security_key: Some("key".to_string()),
+            salt: "salt".to_string(),
+            permissions: 1,
+            lockout_time: -1,
+        }
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("rustcanvas-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn a_locked_database_times_out() {
+        let path = temp_path("locked");
+        DatabaseConnection::new(&path).unwrap();
+
+        // Someone else grabs an exclusive lock and sits on it
+        let other = rusqlite::Connection::open(&path).unwrap();
+        other.execute_batch("BEGIN EXCLUSIVE").unwrap();
+
+        let result = DatabaseConnection::new_with_timeout(&path, Duration::from_millis(50));
+        assert!(matches!(result, Err(DbError::Locked { .. })));
+    }
+
+    #[test]
+    fn the_duplicate_username_index_is_dropped() {
+        let path = temp_path("username-index");
+        DatabaseConnection::new(&path).unwrap();
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch("CREATE UNIQUE INDEX Users_username ON Users (username)")
+            .unwrap();
+        drop(conn);
+
+        let db = DatabaseConnection::new(&path).unwrap();
+        let conn = db.conn().unwrap();
+        let index = conn
+            .prepare("SELECT 1 FROM sqlite_master WHERE name = 'Users_username'")
+            .unwrap()
+            .exists([])
+            .unwrap();
+        assert!(!index);
+
+        // The primary key still keeps names unique
+        db.create_user(&user("alice")).unwrap();
+        assert!(matches!(
+            db.create_user(&user("alice")),
+            Err(DbError::UsernameTaken(_))
+        ));
+    }
+
+    #[test]
+    fn reads_come_from_the_replica() {
+        let (primary, replica) = (temp_path("primary"), temp_path("replica"));
+        let dot = object_with(vec![]);
+
+        let db = DatabaseConnection::new(&primary).unwrap();
+        db.insert_object("canvas-1", &dot).unwrap();
+        // Stand-in for replication: the replica is a snapshot taken now
+        std::fs::copy(&primary, &replica).unwrap();
+        let db = db
+            .with_read_replica(&replica, Duration::from_secs(1), 2)
+            .unwrap();
+
+        // The write lands on the primary, the read comes from the snapshot
+        db.insert_object("canvas-1", &dot).unwrap();
+        assert_eq!(db.get_objects("canvas-1").unwrap().len(), 1);
+        let primary = DatabaseConnection::new(&primary).unwrap();
+        assert_eq!(primary.get_objects("canvas-1").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn users_round_trip_and_names_are_unique() {
+        let db = memory_db();
+        let mut alice = user("alice");
+        assert_eq!(db.get_user("alice").unwrap(), None);
+        db.create_user(&alice).unwrap();
+        assert_eq!(db.get_user("alice").unwrap(), Some(alice.clone()));
+        assert!(
+            matches!(db.create_user(&alice), Err(DbError::UsernameTaken(name)) if name == "alice")
+        );
+
+        alice.lockout_time = 1_700_000_000;
+        assert!(db.update_user(&alice).unwrap());
+        assert_eq!(
+            db.get_user("alice").unwrap().unwrap().lockout_time,
+            1_700_000_000
+        );
+
+        alice.username = "bob".to_string();
+        assert!(!db.update_user(&alice).unwrap());
+    }
+
+    #[test]
+    fn objects_come_back_as_they_went_in() {
+        let db = memory_db();
+        let stroke = DrawnObject {
+            id: 3,
+            num_args: vec![0.1, -2.5e-300, 1e300, 42.0],
+            str_args: vec!["pen".to_string(), "ünïcødé \"quoted\"".to_string()],
+            color_args: vec![(206, 66, 43), (0, 0, 0), (255, 255, 255)],
+            bool_args: vec![true, false],
+        };
+        let row = db.insert_object("canvas-1", &stroke).unwrap();
+        db.insert_object("canvas-2", &stroke).unwrap();
+        assert_eq!(db.get_objects("canvas-1").unwrap(), [(row, stroke)]);
+        assert_eq!(db.count_objects().unwrap(), 2);
+
+        assert!(db.delete_object(row).unwrap());
+        assert!(db.get_objects("canvas-1").unwrap().is_empty());
+        assert!(!db.delete_object(row).unwrap());
     }
 }
@@ -1,13 +1,175 @@
-#[macro_export]
-macro_rules! spawn_tasks {
-    ($state:expr, $($func:expr),* $(,)?) => {{
-        let mut handles = Vec::new();
-        $(
-            let state = $state.clone();
-            handles.push(tokio::spawn($func(state)));
-        )*
-        let task_count = handles.len();
-        tracing::info!("Spawned {} {}", task_count, if task_count == 1 { "task" } else { "tasks" });
-        handles
-    }};
-}
+#[macro_export]
+macro_rules! spawn_tasks {
+    ($state:expr, $($func:expr),* $(,)?) => {{
+        let mut handles = Vec::new();
+        $(
+            let state = $state.clone();
+            handles.push(tokio::spawn($func(state)));
+        )*
+        let task_count = handles.len();
+        tracing::info!("Spawned {} {}", task_count, if task_count == 1 { "task" } else { "tasks" });
+        handles
+    }};
+}
+
+/// Like [`spawn_tasks!`], but each task is named and brought back when it stops.
+///
+/// Tasks are meant to run forever, so returning counts as a failure the same as panicking.
+/// A failed task is restarted up to `max_restarts` times, waiting `backoff` before the first
+/// restart and twice as long before each one after. The returned handles only finish once a
+/// task has used up its restarts.
+///
+/// ```
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// use macros::spawn_supervised_tasks;
+/// use std::time::Duration;
+///
+/// async fn heartbeat(_: ()) {
+///     std::future::pending::<()>().await
+/// }
+///
+/// let handles = spawn_supervised_tasks!((), 3, Duration::from_secs(1), ("heartbeat", heartbeat));
+/// # });
+/// ```
+#[macro_export]
+macro_rules! spawn_supervised_tasks {
+    ($state:expr, $max_restarts:expr, $backoff:expr, $(($name:expr, $func:expr)),* $(,)?) => {{
+        let max_restarts: u32 = $max_restarts;
+        let backoff: std::time::Duration = $backoff;
+        let mut handles = Vec::new();
+        $(
+            let state = $state.clone();
+            handles.push(tokio::spawn(async move {
+                let name = $name;
+                let mut restarts: u32 = 0;
+                // Aborting the supervisor drops this future, and the guard takes the task with it
+                struct AbortOnDrop(tokio::task::AbortHandle);
+                impl Drop for AbortOnDrop {
+                    fn drop(&mut self) {
+                        self.0.abort();
+                    }
+                }
+                loop {
+                    // Its own task, so a panic ends up here instead of taking the supervisor down
+                    let task = tokio::spawn($func(state.clone()));
+                    let _guard = AbortOnDrop(task.abort_handle());
+                    match task.await {
+                        Ok(_) => tracing::error!("Task {} exited", name),
+                        Err(e) => tracing::error!("Task {} crashed: {}", name, e),
+                    }
+                    if restarts >= max_restarts {
+                        tracing::error!("Task {} failed {} times, giving up", name, restarts + 1);
+                        break;
+                    }
+                    let delay = backoff.saturating_mul(2u32.saturating_pow(restarts));
+                    restarts += 1;
+                    tracing::warn!(
+                        "Restarting task {} in {:?} (restart {} of {})",
+                        name, delay, restarts, max_restarts
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+            }));
+        )*
+        let task_count = handles.len();
+        tracing::info!(
+            "Spawned {} supervised {}",
+            task_count,
+            if task_count == 1 { "task" } else { "tasks" }
+        );
+        handles
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use std::time::Duration;
+
+    static FLAKY_RUNS: AtomicU32 = AtomicU32::new(0);
+    static BROKEN_RUNS: AtomicU32 = AtomicU32::new(0);
+
+    // Falls over twice, then settles down and runs for good
+    async fn flaky(_: ()) {
+        if FLAKY_RUNS.fetch_add(1, Ordering::SeqCst) < 2 {
+            panic!("transient failure");
+        }
+        std::future::pending::<()>().await
+    }
+
+    async fn broken(_: ()) {
+        BROKEN_RUNS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[tokio::test]
+    async fn failed_tasks_restart_until_they_run_out() {
+        let mut handles = spawn_supervised_tasks!(
+            (),
+            3,
+            Duration::from_millis(1),
+            ("flaky", flaky),
+            ("broken", broken)
+        );
+
+        // The broken task gives up after its first run plus three restarts...
+        let broken = handles.pop().unwrap();
+        broken.await.unwrap();
+        assert_eq!(BROKEN_RUNS.load(Ordering::SeqCst), 4);
+
+        // ...while the flaky one is still going on its third run
+        while FLAKY_RUNS.load(Ordering::SeqCst) < 3 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert!(!handles[0].is_finished());
+    }
+
+    #[derive(Default)]
+    struct Lifecycle {
+        started: AtomicBool,
+        stopped: AtomicBool,
+    }
+
+    // Marks the task stopped when its future is dropped, which is what aborting it does
+    struct StopOnDrop(Arc<Lifecycle>);
+    impl Drop for StopOnDrop {
+        fn drop(&mut self) {
+            self.0.stopped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    async fn wait_until(flag: &AtomicBool) -> bool {
+        let wait = async {
+            while !flag.load(Ordering::SeqCst) {
+                tokio::task::yield_now().await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), wait)
+            .await
+            .is_ok()
+    }
+
+    async fn forever(lifecycle: Arc<Lifecycle>) {
+        let _guard = StopOnDrop(lifecycle.clone());
+        lifecycle.started.store(true, Ordering::SeqCst);
+        std::future::pending::<()>().await
+    }
+
+    #[tokio::test]
+    async fn aborting_the_supervisor_stops_its_task() {
+        let lifecycle = Arc::new(Lifecycle::default());
+        let handles = spawn_supervised_tasks!(
+            lifecycle.clone(),
+            3,
+            Duration::from_millis(1),
+            ("forever", forever)
+        );
+        assert!(wait_until(&lifecycle.started).await);
+
+        handles[0].abort();
+        assert!(
+            wait_until(&lifecycle.stopped).await,
+            "the supervised task kept running"
+        );
+    }
+}
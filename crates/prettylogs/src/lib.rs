@@ -1,6 +1,23 @@
 //! Pretty logs for RustCanvas.
 
-use tracing_subscriber::{filter::EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
+use std::fmt as stdfmt;
+use std::sync::OnceLock;
+use tracing_subscriber::{
+    Registry, filter::EnvFilter, fmt, layer::SubscriberExt, reload, util::SubscriberInitExt,
+};
+
+/// Swaps the active filter of a subscriber installed by one of the `init_logging*` functions.
+pub type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
+
+// Kept for set_log_filter, so callers don't have to thread the handle around
+static FILTER_HANDLE: OnceLock<LogFilterHandle> = OnceLock::new();
+
+// Wrap `filter` so it can be replaced later, and remember how
+fn reloadable(filter: EnvFilter) -> (reload::Layer<EnvFilter, Registry>, LogFilterHandle) {
+    let (layer, handle) = reload::Layer::new(filter);
+    let _ = FILTER_HANDLE.set(handle.clone());
+    (layer, handle)
+}
 
 /// Initialize the tracing subscriber with custom filtering rules.
 ///
@@ -13,13 +30,108 @@ use tracing_subscriber::{filter::EnvFilter, fmt, layer::SubscriberExt, util::Sub
 /// prettylogs::init_logging();
 /// tracing::info!("This log from your code will be visible");
 /// ```
-pub fn init_logging() {
-    // Create an environment filter that:
-    // 1. Sets external crates to only log at WARN level or higher (always)
-    // 2. Sets our internal crates to log at appropriate level based on build profile:
-    //    - In debug: TRACE level
-    //    - In release: INFO level (skip debug and trace)
+pub fn init_logging() -> LogFilterHandle {
+    let (filter, handle) = reloadable(default_filter());
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(
+            fmt::layer().with_target(true).without_time(), // Remove timestamp from output
+        )
+        .init();
+
+    #[cfg(debug_assertions)]
+    tracing::debug!("Logging initialized with trace level enabled");
+    #[cfg(not(debug_assertions))]
+    tracing::info!("Logging initialized (debug disabled in release mode)");
+    handle
+}
+
+/// Set to `json` to get [`init_logging_json`] output from the binary instead of the pretty
+/// console format.
+pub const ENV_LOG_FORMAT: &str = "RUSTCANVAS_LOG_FORMAT";
+
+/// Like [`init_logging`], but writes one JSON object per event for log aggregators.
+///
+/// Each line carries the timestamp, level, target and the event's fields, with the same
+/// filtering as [`init_logging`].
+///
+/// # Example
+/// ```
+/// prettylogs::init_logging_json();
+/// tracing::info!(connection = 7, "Client joined");
+/// // {"timestamp":"...","level":"INFO","fields":{"message":"Client joined","connection":7},"target":"..."}
+/// ```
+pub fn init_logging_json() -> LogFilterHandle {
+    let (filter, handle) = reloadable(default_filter());
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().json().with_target(true))
+        .init();
+
+    tracing::debug!("JSON logging initialized");
+    handle
+}
+
+/// Why [`set_log_filter`] didn't change anything.
+#[derive(Debug)]
+pub enum LogFilterError {
+    /// None of the `init_logging*` functions has run.
+    NotInitialized,
+    /// The directive doesn't parse.
+    Invalid(tracing_subscriber::filter::ParseError),
+    /// The subscriber is gone.
+    Reload(reload::Error),
+}
+
+impl stdfmt::Display for LogFilterError {
+    fn fmt(&self, f: &mut stdfmt::Formatter) -> stdfmt::Result {
+        match self {
+            LogFilterError::NotInitialized => write!(f, "logging hasn't been initialized"),
+            LogFilterError::Invalid(e) => write!(f, "invalid filter directive: {}", e),
+            LogFilterError::Reload(e) => write!(f, "couldn't swap the log filter: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for LogFilterError {}
+
+/// Replace the running log filter, e.g. to trace one crate during an investigation.
+///
+/// `directive` uses the same syntax as `RUST_LOG`. An empty one goes back to the default
+/// filter. A directive that doesn't parse is rejected and the current filter stays.
+///
+/// # Example
+/// ```
+/// prettylogs::init_logging();
+/// prettylogs::set_log_filter("db=trace,warn").unwrap();
+/// assert!(prettylogs::set_log_filter("db=loud").is_err());
+///
+/// // Back to normal
+/// prettylogs::set_log_filter("").unwrap();
+/// ```
+pub fn set_log_filter(directive: &str) -> Result<(), LogFilterError> {
+    let handle = FILTER_HANDLE.get().ok_or(LogFilterError::NotInitialized)?;
+    let filter = if directive.trim().is_empty() {
+        default_filter()
+    } else {
+        EnvFilter::builder()
+            .parse(directive)
+            .map_err(LogFilterError::Invalid)?
+    };
+    handle.reload(filter).map_err(LogFilterError::Reload)?;
+    tracing::info!(
+        "Log filter set to '{}'",
+        if directive.trim().is_empty() {
+            "default"
+        } else {
+            directive
+        }
+    );
+    Ok(())
+}
 
+// Our crates log at TRACE in debug builds and INFO in release, everything else at WARN
+fn default_filter() -> EnvFilter {
     // Determine minimum log level based on build configuration
     #[cfg(debug_assertions)]
     let internal_level = "trace";
@@ -32,23 +144,10 @@ pub fn init_logging() {
         internal_level
     );
 
-    let filter = EnvFilter::builder()
+    EnvFilter::builder()
         // Add any specific crates from our project here to enable appropriate logging
         .parse(&filter_directive)
-        .expect("Invalid filter directive");
-
-    // Initialize the tracing subscriber with the filter and no time/date
-    tracing_subscriber::registry()
-        .with(
-            fmt::layer().with_target(true).without_time(), // Remove timestamp from output
-        )
-        .with(filter)
-        .init();
-
-    #[cfg(debug_assertions)]
-    tracing::debug!("Logging initialized with trace level enabled");
-    #[cfg(not(debug_assertions))]
-    tracing::info!("Logging initialized (debug disabled in release mode)");
+        .expect("Invalid filter directive")
 }
 
 /// Initialize the tracing subscriber with a custom filter string.
@@ -60,12 +159,14 @@ pub fn init_logging() {
 ///
 /// * `filter_str` - A custom filter directive string
 ///
+/// Fails with [`LogFilterError::Invalid`], installing nothing, if the directive doesn't parse.
+///
 /// # Example
 /// ```
 /// // Enable debug for our code, info for some_dependency, and warn for everything else
-/// prettylogs::init_logging_with_filter("rustcanvas=debug,some_dependency=info,warn");
+/// prettylogs::init_logging_with_filter("rustcanvas=debug,some_dependency=info,warn").unwrap();
 /// ```
-pub fn init_logging_with_filter(filter_str: &str) {
+pub fn init_logging_with_filter(filter_str: &str) -> Result<LogFilterHandle, LogFilterError> {
     // In release mode, we'll respect the provided filter but ensure debug logs are disabled
     // for any crates that don't explicitly override this
     #[cfg(not(debug_assertions))]
@@ -75,18 +176,20 @@ pub fn init_logging_with_filter(filter_str: &str) {
         filter_str.to_string()
     };
 
-    let filter = EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| EnvFilter::try_new(filter_str).expect("Invalid filter directive"));
+    // Usually typed on the command line, so a typo is an error to report, not a panic
+    let filter = EnvFilter::try_new(filter_str).map_err(LogFilterError::Invalid)?;
 
+    let (filter, handle) = reloadable(filter);
     tracing_subscriber::registry()
+        .with(filter)
         .with(
             fmt::layer().with_target(true).without_time(), // Remove timestamp from output
         )
-        .with(filter)
         .init();
 
     #[cfg(debug_assertions)]
     tracing::debug!("Logging initialized with custom filter: {}", filter_str);
     #[cfg(not(debug_assertions))]
     tracing::info!("Logging initialized with custom filter (debug disabled in release mode)");
+    Ok(handle)
 }
@@ -0,0 +1,37 @@
+// Command line arguments - everything is optional, no args runs exactly like before
+use clap::Parser;
+use config::CliOverrides;
+
+#[derive(Parser, Debug)]
+#[command(version, about = "A collaborative canvas server")]
+pub struct Cli {
+    /// Config file to use. A name ending in .json or .toml is read as exactly that file,
+    /// anything else is tried with .json and then .toml appended
+    #[arg(long, value_name = "PATH", default_value = "config")]
+    pub config: String,
+
+    /// Port to listen on, over the config file and RUSTCANVAS_PORT
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    /// Interface to bind, over the config file and RUSTCANVAS_INTERFACE
+    #[arg(long)]
+    pub interface: Option<String>,
+
+    /// Log filter in RUST_LOG syntax, e.g. "db=trace,info", over RUST_LOG itself
+    #[arg(long, value_name = "DIRECTIVE")]
+    pub log_filter: Option<String>,
+
+    /// Report what first-run setup would do, then exit
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+impl Cli {
+    pub fn overrides(&self) -> CliOverrides {
+        CliOverrides {
+            port: self.port,
+            interface: self.interface.clone(),
+        }
+    }
+}
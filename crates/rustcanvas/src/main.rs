@@ -1,44 +1,299 @@
+mod cli;
+
 use appstate::AppState;
-use config::load_config;
+use clap::Parser;
+use cli::Cli;
+use config::{ConfigIssue, ConfigValidationError, DatabaseBackend, load_config_with, watch_config};
 use db::DatabaseConnection;
-use macros::spawn_tasks;
-use prettylogs::init_logging;
-use std::{error::Error, path::Path};
-use tokio::{select, task::JoinHandle};
+use macros::spawn_supervised_tasks;
+use prettylogs::{
+    ENV_LOG_FORMAT, init_logging, init_logging_json, init_logging_with_filter, set_log_filter,
+};
+use std::{error::Error, time::Duration};
+use tokio::task::JoinHandle;
 use tracing::*;
 use webserver::start_webserver;
 
+// Total time all shutdown hooks get before we exit anyway
+const SHUTDOWN_HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    // Parsed before anything else so --help and bad arguments exit straight away
+    let cli = Cli::parse();
     // Initialize logging first so all subsequent logs are captured
-    init_logging();
+    // Machine-readable logs for aggregators, chosen before the config is even read
+    // The filter handle isn't needed here, /admin/log-filter goes through set_log_filter
+    // --log-filter wins, RUST_LOG still works without it
+    let log_filter = cli
+        .log_filter
+        .clone()
+        .or_else(|| std::env::var("RUST_LOG").ok());
+    let _ = match (std::env::var(ENV_LOG_FORMAT).as_deref(), &log_filter) {
+        (Ok("json"), filter) => {
+            let handle = init_logging_json();
+            if let Some(filter) = filter {
+                set_log_filter(filter)?;
+            }
+            handle
+        }
+        (_, Some(filter)) => init_logging_with_filter(filter)?,
+        (_, None) => init_logging(),
+    };
     info!("RustCanvas starting up");
-    let conf = load_config("config");
+    // Dry run only reports what first-run setup would do, then exits
+    let dry_run = cli.dry_run;
+    let config_path = cli.config.clone();
+    let mut conf = load_config_with(&config_path, dry_run);
+    // Containers often can't mount a config file, so the environment overrides it
+    conf.apply_env_overrides()?;
+    // And whoever started us by hand has the last word
+    conf.apply_cli_overrides(&cli.overrides());
     debug!("Configuration loaded");
+    if let Err(issues) = conf.validate_all() {
+        for issue in &issues {
+            if issue.is_error() {
+                error!("Config error: {}", issue);
+            } else {
+                warn!("Config warning: {}", issue);
+            }
+        }
+        let errors: Vec<ConfigIssue> = issues.into_iter().filter(ConfigIssue::is_error).collect();
+        if !errors.is_empty() {
+            // main prints errors with Debug, so hand it the readable message
+            return Err(ConfigValidationError { issues: errors }.to_string().into());
+        }
+    }
     info!("Attempting to load Database...");
-    let pathstr = conf.database_path.clone();
-    let path = Path::new(&pathstr);
-    let db = DatabaseConnection::new(path)?;
+    if dry_run {
+        match conf.database_backend()? {
+            DatabaseBackend::Sqlite(path) => {
+                let action = if path.exists() { "open" } else { "create" };
+                info!(
+                    "Dry run: would {} SQLite database {} and apply the init.sql schema",
+                    action,
+                    path.display()
+                );
+            }
+        }
+        info!("Dry run complete, exiting without starting the server");
+        return Ok(());
+    }
+
+    let busy_timeout = Duration::from_millis(conf.database_busy_timeout_ms);
+    let db = match conf.database_backend()? {
+        DatabaseBackend::Sqlite(path) => {
+            // Validation only checked the directory could be made, so make it
+            if let Some(dir) = path.parent()
+                && !dir.as_os_str().is_empty()
+            {
+                std::fs::create_dir_all(dir)?;
+            }
+            DatabaseConnection::open(&path, busy_timeout, conf.database_pool_size)?
+        }
+    };
+    let db = match conf.read_database_backend()? {
+        Some(DatabaseBackend::Sqlite(path)) => {
+            info!("Sending reads to {}", path.display());
+            db.with_read_replica(&path, busy_timeout, conf.database_pool_size)?
+        }
+        None => db,
+    };
 
+    let max_restarts = conf.task_max_restarts;
+    let restart_backoff = Duration::from_millis(conf.task_restart_backoff_ms);
     let state: AppState = AppState::new(conf, db);
-    let handles: Vec<JoinHandle<()>> = spawn_tasks!(state.clone(), start_webserver);
-    // Wait for any task to complete, which means it failed, all of my tasks exit on failure only
-    if !handles.is_empty() {
-        select! {
-            (completed_task, index, _) = futures::future::select_all(handles) => {
-                match completed_task {
-                    Ok(_) => {
-                        error!("Task {} completed unexpectedly. Tasks should run indefinitely.", index);
-                    }
-                    Err(err) => {
-                        error!("Task {} terminated with an error: {:?}", index, err);
-                    }
-                }
+    info!("Server instance {}", state.instance_id);
+    tokio::spawn(watch_config(
+        config_path,
+        state.config.clone(),
+        cli.overrides(),
+    ));
+    #[cfg(unix)]
+    tokio::spawn(snapshot_on_signal(state.clone()));
+    let handles: Vec<JoinHandle<()>> = spawn_supervised_tasks!(
+        state.clone(),
+        max_restarts,
+        restart_backoff,
+        ("webserver", start_webserver)
+    );
+    let result = tokio::select! {
+        result = wait_for_first_exit(handles) => result,
+        _ = state.exit_requested() => {
+            info!("Exit requested, shutting down");
+            Ok(())
+        }
+        _ = shutdown_signal() => {
+            info!("Shutdown signal received, closing connections");
+            Ok(())
+        }
+    };
+    let unflushed = state.shutdown().await;
+    if unflushed > 0 {
+        warn!("{} connections did not close cleanly in time", unflushed);
+    }
+    run_shutdown_hooks(&state).await;
+    result
+}
+
+// SIGUSR1 dumps a diagnostic snapshot to snapshot_path, without stopping anything
+#[cfg(unix)]
+async fn snapshot_on_signal(state: AppState) {
+    use tokio::signal::unix::{SignalKind, signal};
+    let mut sigusr1 = match signal(SignalKind::user_defined1()) {
+        Ok(sigusr1) => sigusr1,
+        Err(e) => {
+            warn!("Snapshots disabled, can't listen for SIGUSR1: {}", e);
+            return;
+        }
+    };
+    while sigusr1.recv().await.is_some() {
+        let path = state.config.lock().await.snapshot_path.clone();
+        match state.write_snapshot(std::path::Path::new(&path)).await {
+            Ok(()) => info!("Wrote diagnostic snapshot to {}", path),
+            Err(e) => error!("Failed to write snapshot to {}: {}", path, e),
+        }
+    }
+}
+
+// Ctrl+C everywhere, plus SIGTERM where there is one (service managers, containers)
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
             }
-            else => {
-                info!("All tasks completed successfully");
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
             }
         }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+// Give registered hooks a chance to flush, but never hang the exit on them
+async fn run_shutdown_hooks(state: &AppState) {
+    let unfinished = state.shutdown_hooks.run(SHUTDOWN_HOOK_TIMEOUT).await;
+    if !unfinished.is_empty() {
+        warn!(
+            "Shutdown hooks did not finish within {:?}: {}",
+            SHUTDOWN_HOOK_TIMEOUT,
+            unfinished.join(", ")
+        );
+    }
+}
+
+// Wait for any task to complete, which means it failed, all of my tasks exit on failure only
+// The others are aborted, so nothing keeps running against a server that's shutting down
+// An empty task list would return straight away, so treat it as a startup mistake instead
+async fn wait_for_first_exit(handles: Vec<JoinHandle<()>>) -> Result<(), Box<dyn Error>> {
+    if handles.is_empty() {
+        warn!("No tasks spawned; nothing to run");
+        return Err("no tasks were spawned".into());
+    }
+
+    // select_all is fine with a single handle, it just resolves when that one does
+    let (completed_task, index, remaining) = futures::future::select_all(handles).await;
+    // Dropping a JoinHandle only detaches the task, so the rest are stopped explicitly
+    for handle in remaining {
+        handle.abort();
+    }
+    match completed_task {
+        Ok(_) => {
+            error!(
+                "Task {} completed unexpectedly. Tasks should run indefinitely.",
+                index
+            );
+        }
+        Err(err) => {
+            error!("Task {} terminated with an error: {:?}", index, err);
+        }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::oneshot;
+
+    // A task that runs until aborted; the receiver errors once it's gone
+    fn long_running() -> (JoinHandle<()>, oneshot::Receiver<()>) {
+        let (alive, gone) = oneshot::channel::<()>();
+        let handle = tokio::spawn(async move {
+            let _alive = alive;
+            std::future::pending::<()>().await;
+        });
+        (handle, gone)
+    }
+
+    async fn assert_cancelled(gone: oneshot::Receiver<()>) {
+        let result = tokio::time::timeout(Duration::from_secs(1), gone).await;
+        assert!(matches!(result, Ok(Err(_))), "task was left running");
+    }
+
+    #[tokio::test]
+    async fn first_exit_cancels_the_other_tasks() {
+        let (a, a_gone) = long_running();
+        let (b, b_gone) = long_running();
+        let exits = tokio::spawn(async {});
+
+        assert!(wait_for_first_exit(vec![a, exits, b]).await.is_ok());
+        assert_cancelled(a_gone).await;
+        assert_cancelled(b_gone).await;
+    }
+
+    #[tokio::test]
+    async fn first_panic_cancels_the_other_tasks() {
+        let (a, a_gone) = long_running();
+        let panics = tokio::spawn(async { panic!("task blew up") });
+
+        assert!(wait_for_first_exit(vec![a, panics]).await.is_ok());
+        assert_cancelled(a_gone).await;
+    }
+
+    #[tokio::test]
+    async fn first_exit_cancels_supervised_tasks_too() {
+        // The supervisor hands its task a clone of the state, so the sender travels in it
+        async fn holds_on(alive: Arc<Mutex<Option<oneshot::Sender<()>>>>) {
+            let _alive = alive.lock().unwrap().take();
+            std::future::pending::<()>().await;
+        }
+        async fn exits(_: Arc<Mutex<Option<oneshot::Sender<()>>>>) {}
+
+        let (alive, gone) = oneshot::channel::<()>();
+        let state = Arc::new(Mutex::new(Some(alive)));
+        let handles = spawn_supervised_tasks!(
+            state,
+            0,
+            Duration::ZERO,
+            ("holds_on", holds_on),
+            ("exits", exits)
+        );
+
+        assert!(wait_for_first_exit(handles).await.is_ok());
+        assert_cancelled(gone).await;
+    }
+
+    #[tokio::test]
+    async fn no_tasks_is_an_error() {
+        assert!(wait_for_first_exit(Vec::new()).await.is_err());
+    }
+}
@@ -0,0 +1,85 @@
+//! `--dry-run` reports what first-run setup would do, so it must not touch anything on disk.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// A scratch directory for one test, removed again when dropped
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("rustcanvas-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+// Runs the server binary with --dry-run against `config`, pointed at `database`
+fn dry_run(config: &Path, database: &Path) {
+    let output = Command::new(env!("CARGO_BIN_EXE_rustcanvas"))
+        .arg("--config")
+        .arg(config)
+        .arg("--dry-run")
+        .env("RUSTCANVAS_DATABASE_PATH", database)
+        .env_remove("RUSTCANVAS_PORT")
+        .env_remove("RUSTCANVAS_INTERFACE")
+        .env_remove("RUSTCANVAS_ADMIN_TOKEN")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "dry run failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+fn listing(dir: &Path) -> Vec<(String, Vec<u8>)> {
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, std::fs::read(&path).unwrap())
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn dry_run_creates_neither_database_nor_config() {
+    let dir = TempDir::new("dry-run-fresh");
+    let database = dir.0.join("data").join("canvas.db");
+
+    dry_run(&dir.0.join("config.json"), &database);
+    assert!(listing(&dir.0).is_empty());
+}
+
+#[test]
+fn dry_run_leaves_an_existing_database_unchanged() {
+    let dir = TempDir::new("dry-run-existing");
+    let database = dir.0.join("canvas.db");
+    let db = db::DatabaseConnection::new(&database).unwrap();
+    let stroke = db::DrawnObject {
+        id: 1,
+        num_args: vec![0.5, 2.0],
+        str_args: Vec::new(),
+        color_args: vec![(255, 0, 0)],
+        bool_args: Vec::new(),
+    };
+    db.insert_object("canvas-1", &stroke).unwrap();
+    drop(db);
+    let config = dir.0.join("config.json");
+    std::fs::write(&config, "{}").unwrap();
+    let before = listing(&dir.0);
+
+    dry_run(&config, &database);
+    assert_eq!(listing(&dir.0), before);
+}
@@ -0,0 +1,94 @@
+//! Pluggable time source for anything that compares against "now".
+//!
+//! Production code should use [`SystemClock`]. Tests can swap in [`MockClock`] and move time
+//! forward by hand instead of sleeping, so expiry logic runs instantly and deterministically.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// A source of the current time that can be shared across tasks.
+pub trait Clock: Send + Sync {
+    /// Monotonic time, for measuring how long something took or has been idle.
+    fn now(&self) -> Instant;
+
+    /// Wall-clock time, for timestamps that leave the process.
+    fn system_time(&self) -> SystemTime;
+}
+
+/// The real time, straight from the operating system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when told to. Never use this outside tests.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use utils::clock::{Clock, MockClock};
+///
+/// let clock = MockClock::new();
+/// let session_expires = clock.now() + Duration::from_secs(30 * 60);
+/// assert!(clock.now() < session_expires);
+///
+/// // Half an hour passes in no time at all
+/// clock.advance(Duration::from_secs(30 * 60));
+/// assert!(clock.now() >= session_expires);
+/// assert_eq!(
+///     clock.system_time().duration_since(MockClock::new().system_time()).unwrap(),
+///     Duration::from_secs(30 * 60)
+/// );
+/// ```
+#[derive(Debug)]
+pub struct MockClock {
+    start: Instant,
+    start_system: SystemTime,
+    elapsed: Mutex<Duration>,
+}
+
+impl MockClock {
+    /// Starts at the current instant and at a fixed wall-clock time, so timestamps are
+    /// repeatable between runs.
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            start_system: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    /// Moves both the monotonic and the wall clock forward by `by`.
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().expect("Mock clock poisoned") += by;
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().expect("Mock clock poisoned")
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.start_system + self.elapsed()
+    }
+}
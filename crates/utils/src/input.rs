@@ -1,155 +1,317 @@
-//! Input utilities for handling keyboard input in terminal applications.
-
-use std::io::{self, Read};
-
-/// A function similar to the DOS batch CHOICE command that waits for a keypress
-/// from a specified set of valid choices.
-///
-/// # Arguments
-///
-/// * `choices` - A string containing all valid characters to listen for.
-/// * `case_sensitive` - Whether the choices are case sensitive.
-/// * `prompt` - An optional prompt to display before waiting for input.
-///
-/// # Returns
-///
-/// Returns the character that was pressed as a `char`.
-///
-/// # Examples
-///
-
-/// ```no_run
-
-/// use utils::input::choice;
-
-///
-
-/// // Wait for user to press Y, N, or Escape
-
-/// let result = choice("YNy\x1B", false, Some("Continue? [Y/N] "));
-
-/// match result {
-
-///     'Y' | 'y' => println!("User chose Yes"),
-
-///     'N' | 'n' => println!("User chose No"),
-
-///     '\x1B' => println!("User pressed Escape"),
-
-///     _ => unreachable!(),
-
-/// }
-
-/// ```
-
-pub fn choice(choices: &str, case_sensitive: bool, prompt: Option<&str>) -> char {
-    // Print prompt if provided
-    if let Some(text) = prompt {
-        print!("{}", text);
-        let _ = io::Write::flush(&mut io::stdout());
-    }
-
-    // Prepare choices for comparison
-    let choices_vec: Vec<char> = if case_sensitive {
-        choices.chars().collect()
-    } else {
-        let mut chars = Vec::new();
-        for c in choices.chars() {
-            chars.push(c.to_lowercase().next().unwrap());
-            chars.push(c.to_uppercase().next().unwrap());
-        }
-        chars
-    };
-
-    // Read single key presses until a valid choice is made
-    let stdin = io::stdin();
-    let mut stdin = stdin.lock();
-    let mut buffer = [0; 1];
-
-    loop {
-        if let Ok(1) = stdin.read(&mut buffer) {
-            let pressed = buffer[0] as char;
-            if choices_vec.contains(&pressed)
-                || (!case_sensitive
-                    && choices_vec.contains(&pressed.to_lowercase().next().unwrap()))
-            {
-                return pressed;
-            }
-            // Invalid key, ignore and continue listening
-        }
-    }
-}
-
-/// A version of the choice function that uses crossterm for better
-/// terminal handling. Must be used in a context where terminal raw mode
-/// is appropriate.
-///
-/// Requires the `crossterm` feature to be enabled.
-#[cfg(feature = "crossterm")]
-pub fn crossterm_choice(
-    choices: &str,
-    case_sensitive: bool,
-    prompt: Option<&str>,
-) -> io::Result<char> {
-    use crossterm::{
-        event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
-        terminal, ExecutableCommand,
-    };
-    use std::io::Write;
-
-    // Print prompt if provided
-    if let Some(text) = prompt {
-        print!("{}", text);
-        io::stdout().flush()?;
-    }
-
-    // Prepare choices for comparison
-    let choices_vec: Vec<char> = if case_sensitive {
-        choices.chars().collect()
-    } else {
-        let mut chars = Vec::new();
-        for c in choices.chars() {
-            chars.push(c.to_lowercase().next().unwrap());
-            chars.push(c.to_uppercase().next().unwrap());
-        }
-        chars
-    };
-
-    // Enable raw mode
-    terminal::enable_raw_mode()?;
-
-    let result = loop {
-        if let Event::Key(KeyEvent { code, kind, .. }) = event::read()? {
-            // Only process key press events (not key releases)
-            if kind == KeyEventKind::Press {
-                match code {
-                    KeyCode::Char(c) => {
-                        if choices_vec.contains(&c)
-                            || (!case_sensitive
-                                && choices_vec.contains(&c.to_lowercase().next().unwrap()))
-                        {
-                            break Ok(c);
-                        }
-                    }
-                    KeyCode::Esc => {
-                        if choices.contains('\x1B') {
-                            break Ok('\x1B');
-                        }
-                    }
-                    KeyCode::Enter => {
-                        if choices.contains('\r') || choices.contains('\n') {
-                            break Ok('\n');
-                        }
-                    }
-                    // Handle other special keys if needed
-                    _ => {}
-                }
-            }
-        }
-    };
-
-    // Disable raw mode
-    terminal::disable_raw_mode()?;
-
-    result
-}
+//! Input utilities for handling keyboard input in terminal applications.
+
+use std::io::{self, Read};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+#[cfg(feature = "crossterm")]
+use std::time::Instant;
+
+// Every case variant of the choices when case doesn't matter
+fn expand_choices(choices: &str, case_sensitive: bool) -> Vec<char> {
+    if case_sensitive {
+        choices.chars().collect()
+    } else {
+        let mut chars = Vec::new();
+        for c in choices.chars() {
+            chars.push(c.to_lowercase().next().unwrap());
+            chars.push(c.to_uppercase().next().unwrap());
+        }
+        chars
+    }
+}
+
+/// A function similar to the DOS batch CHOICE command that waits for a keypress
+/// from a specified set of valid choices.
+///
+/// # Arguments
+///
+/// * `choices` - A string containing all valid characters to listen for.
+/// * `case_sensitive` - Whether the choices are case sensitive.
+/// * `prompt` - An optional prompt to display before waiting for input.
+///
+/// # Returns
+///
+/// Returns the character that was pressed as a `char`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use utils::input::choice;
+///
+/// // Wait for user to press Y, N, or Escape
+/// let result = choice("YNy\x1B", false, Some("Continue? [Y/N] "));
+/// match result {
+///     'Y' | 'y' => println!("User chose Yes"),
+///     'N' | 'n' => println!("User chose No"),
+///     '\x1B' => println!("User pressed Escape"),
+///     _ => unreachable!(),
+/// }
+/// ```
+pub fn choice(choices: &str, case_sensitive: bool, prompt: Option<&str>) -> char {
+    // Print prompt if provided
+    if let Some(text) = prompt {
+        print!("{}", text);
+        let _ = io::Write::flush(&mut io::stdout());
+    }
+
+    // Prepare choices for comparison
+    let choices_vec = expand_choices(choices, case_sensitive);
+
+    // Read single key presses until a valid choice is made
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+    let mut buffer = [0; 1];
+
+    loop {
+        if let Ok(1) = stdin.read(&mut buffer) {
+            let pressed = buffer[0] as char;
+            if choices_vec.contains(&pressed)
+                || (!case_sensitive
+                    && choices_vec.contains(&pressed.to_lowercase().next().unwrap()))
+            {
+                return pressed;
+            }
+            // Invalid key, ignore and continue listening
+        }
+    }
+}
+
+/// Like [`choice`], but gives up and returns `default` if no valid key arrives within
+/// `timeout`.
+///
+/// Meant for prompts that may run unattended, like in CI or under a service manager. If
+/// stdin is closed or not readable at all, `default` is returned right away rather than
+/// after the full timeout. The prompt is printed whether or not anyone is there to see it.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use utils::input::choice_with_timeout;
+///
+/// // Assume "yes" if nobody answers within ten seconds
+/// let result = choice_with_timeout("yn", false, Some("Continue? [Y/n] "), Duration::from_secs(10), 'y');
+/// assert!("yYnN".contains(result));
+/// ```
+pub fn choice_with_timeout(
+    choices: &str,
+    case_sensitive: bool,
+    prompt: Option<&str>,
+    timeout: Duration,
+    default: char,
+) -> char {
+    // Print prompt if provided
+    if let Some(text) = prompt {
+        print!("{}", text);
+        let _ = io::Write::flush(&mut io::stdout());
+    }
+
+    choice_from_reader_with_timeout(io::stdin(), choices, case_sensitive, timeout, default)
+}
+
+/// The reading half of [`choice_with_timeout`], taking keys from any reader instead of stdin.
+///
+/// The reader is drained on a background thread, so a read that blocks never holds up the
+/// caller past `timeout`. When the timeout wins, that thread is left blocked on its read and
+/// simply goes away with the process - fine for a one-off prompt, but not something to call
+/// in a loop on a reader that may never produce anything.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use utils::input::choice_from_reader_with_timeout;
+///
+/// // Keys that aren't choices are skipped, the first one that is wins
+/// let piped: &'static [u8] = b"x?T\nj";
+/// assert_eq!(choice_from_reader_with_timeout(piped, "jt", false, Duration::from_secs(5), 'j'), 'T');
+/// ```
+pub fn choice_from_reader_with_timeout<R: Read + Send + 'static>(
+    mut reader: R,
+    choices: &str,
+    case_sensitive: bool,
+    timeout: Duration,
+    default: char,
+) -> char {
+    let choices_vec = expand_choices(choices, case_sensitive);
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut buffer = [0; 1];
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(1) => {
+                    let pressed = buffer[0] as char;
+                    if choices_vec.contains(&pressed) {
+                        let _ = tx.send(pressed);
+                        return;
+                    }
+                    // Invalid key, ignore and continue listening
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                // End of input or a broken reader - dropping tx tells the caller to stop waiting
+                _ => return,
+            }
+        }
+    });
+
+    rx.recv_timeout(timeout).unwrap_or(default)
+}
+
+/// A version of the choice function that uses crossterm for better
+/// terminal handling. Must be used in a context where terminal raw mode
+/// is appropriate.
+///
+/// Requires the `crossterm` feature to be enabled.
+#[cfg(feature = "crossterm")]
+pub fn crossterm_choice(
+    choices: &str,
+    case_sensitive: bool,
+    prompt: Option<&str>,
+) -> io::Result<char> {
+    use crossterm::{
+        event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
+        terminal, ExecutableCommand,
+    };
+    use std::io::Write;
+
+    // Print prompt if provided
+    if let Some(text) = prompt {
+        print!("{}", text);
+        io::stdout().flush()?;
+    }
+
+    // Prepare choices for comparison
+    let choices_vec = expand_choices(choices, case_sensitive);
+
+    // Enable raw mode
+    terminal::enable_raw_mode()?;
+
+    let result = loop {
+        if let Event::Key(KeyEvent { code, kind, .. }) = event::read()? {
+            // Only process key press events (not key releases)
+            if kind == KeyEventKind::Press {
+                match code {
+                    KeyCode::Char(c) => {
+                        if choices_vec.contains(&c)
+                            || (!case_sensitive
+                                && choices_vec.contains(&c.to_lowercase().next().unwrap()))
+                        {
+                            break Ok(c);
+                        }
+                    }
+                    KeyCode::Esc => {
+                        if choices.contains('\x1B') {
+                            break Ok('\x1B');
+                        }
+                    }
+                    KeyCode::Enter => {
+                        if choices.contains('\r') || choices.contains('\n') {
+                            break Ok('\n');
+                        }
+                    }
+                    // Handle other special keys if needed
+                    _ => {}
+                }
+            }
+        }
+    };
+
+    // Disable raw mode
+    terminal::disable_raw_mode()?;
+
+    result
+}
+
+/// [`crossterm_choice`] with a timeout: returns `default` if no valid key is pressed within
+/// `timeout`.
+///
+/// Requires the `crossterm` feature to be enabled.
+#[cfg(feature = "crossterm")]
+pub fn crossterm_choice_with_timeout(
+    choices: &str,
+    case_sensitive: bool,
+    prompt: Option<&str>,
+    timeout: Duration,
+    default: char,
+) -> io::Result<char> {
+    use crossterm::{
+        event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
+        terminal,
+    };
+    use std::io::Write;
+
+    // Print prompt if provided
+    if let Some(text) = prompt {
+        print!("{}", text);
+        io::stdout().flush()?;
+    }
+
+    let choices_vec = expand_choices(choices, case_sensitive);
+    let deadline = Instant::now() + timeout;
+
+    terminal::enable_raw_mode()?;
+
+    let result = loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        // poll returns false once the remaining time is up without an event
+        match event::poll(remaining) {
+            Ok(false) => break Ok(default),
+            Ok(true) => {}
+            Err(e) => break Err(e),
+        }
+        let event = match event::read() {
+            Ok(event) => event,
+            Err(e) => break Err(e),
+        };
+        if let Event::Key(KeyEvent {
+            code,
+            kind: KeyEventKind::Press,
+            ..
+        }) = event
+        {
+            match code {
+                KeyCode::Char(c) if choices_vec.contains(&c) => break Ok(c),
+                KeyCode::Esc if choices.contains('\x1B') => break Ok('\x1B'),
+                KeyCode::Enter if choices.contains('\r') || choices.contains('\n') => {
+                    break Ok('\n')
+                }
+                _ => {}
+            }
+        }
+    };
+
+    // Leave raw mode even if reading failed
+    terminal::disable_raw_mode()?;
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::time::Instant;
+
+    #[test]
+    fn input_without_a_valid_key_falls_back_straight_away() {
+        let start = Instant::now();
+        let piped: &'static [u8] = b"nope";
+        let key = choice_from_reader_with_timeout(piped, "jt", false, Duration::from_secs(5), 'j');
+        assert_eq!(key, 'j');
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn silent_input_times_out_to_the_default() {
+        let (reader, mut writer) = std::io::pipe().unwrap();
+        let start = Instant::now();
+        let key =
+            choice_from_reader_with_timeout(reader, "jt", false, Duration::from_millis(50), 't');
+        assert_eq!(key, 't');
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        // Typing after the deadline changes nothing
+        let _ = writer.write_all(b"j");
+    }
+}
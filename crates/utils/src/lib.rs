@@ -1,3 +1,7 @@
-//! Utility functions for the RustCanvas application.
-
-pub mod input;
+//! Utility functions for the RustCanvas application.
+
+pub mod clock;
+pub mod input;
+pub mod random;
+pub mod rate_limit;
+pub mod sampling;
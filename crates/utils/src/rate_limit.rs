@@ -0,0 +1,141 @@
+//! Token-bucket rate limiting, one bucket per key.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// How often idle buckets are swept out, at most.
+const SWEEP_EVERY: Duration = Duration::from_secs(60);
+
+/// A token bucket per key: each key may spend `burst` tokens at once, refilled at `per_minute`.
+///
+/// Time is passed in rather than read, so callers can drive it from a
+/// [`Clock`](crate::clock::Clock). Buckets that have refilled completely are forgotten, since
+/// they behave exactly like a key that was never seen - the map only holds recently active keys.
+///
+/// # Examples
+///
+/// ```
+/// use utils::clock::{Clock, MockClock};
+/// use utils::rate_limit::RateLimiter;
+///
+/// let clock = MockClock::new();
+/// let mut limiter = RateLimiter::new();
+///
+/// // A burst of 2, then one more every 30 seconds
+/// if !limiter.check("203.0.113.7", 2, 2, clock.now()) {
+///     println!("slow down");
+/// }
+/// ```
+#[derive(Debug)]
+pub struct RateLimiter<K> {
+    buckets: HashMap<K, Bucket>,
+    last_sweep: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refilled(&self, burst: f64, per_sec: f64, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * per_sec).min(burst)
+    }
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    /// Creates a limiter with no buckets.
+    pub fn new() -> Self {
+        Self {
+            buckets: HashMap::new(),
+            last_sweep: None,
+        }
+    }
+
+    /// Takes a token from `key`'s bucket. Returns `false` if it's empty and the event should
+    /// be refused.
+    ///
+    /// The rate is passed on every call so it can change while running; a bucket that's
+    /// fuller than a new, smaller `burst` is simply capped.
+    pub fn check(&mut self, key: K, burst: u32, per_minute: u32, now: Instant) -> bool {
+        if self
+            .last_sweep
+            .is_none_or(|last| now.saturating_duration_since(last) >= SWEEP_EVERY)
+        {
+            self.sweep(burst, per_minute, now);
+        }
+
+        let burst = f64::from(burst);
+        let per_sec = f64::from(per_minute) / 60.0;
+        let bucket = self.buckets.entry(key).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        bucket.tokens = bucket.refilled(burst, per_sec, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Forgets every bucket that has refilled by `now`. [`check`](Self::check) does this on
+    /// its own about once a minute.
+    pub fn sweep(&mut self, burst: u32, per_minute: u32, now: Instant) {
+        let burst = f64::from(burst);
+        let per_sec = f64::from(per_minute) / 60.0;
+        self.buckets
+            .retain(|_, bucket| bucket.refilled(burst, per_sec, now) < burst);
+        self.last_sweep = Some(now);
+    }
+
+    /// Number of keys currently being tracked.
+    pub fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// Whether no keys are being tracked.
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+}
+
+impl<K: Hash + Eq> Default for RateLimiter<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+
+    #[test]
+    fn buckets_refill_per_key_and_are_swept_when_full() {
+        let clock = MockClock::new();
+        let mut limiter = RateLimiter::new();
+
+        // A burst of 2, then one more every 30 seconds
+        assert!(limiter.check("203.0.113.7", 2, 2, clock.now()));
+        assert!(limiter.check("203.0.113.7", 2, 2, clock.now()));
+        assert!(!limiter.check("203.0.113.7", 2, 2, clock.now()));
+
+        // Other keys have their own bucket
+        assert!(limiter.check("198.51.100.1", 2, 2, clock.now()));
+
+        clock.advance(Duration::from_secs(30));
+        assert!(limiter.check("203.0.113.7", 2, 2, clock.now()));
+        assert!(!limiter.check("203.0.113.7", 2, 2, clock.now()));
+
+        // Once every bucket has refilled, nothing is kept
+        clock.advance(Duration::from_secs(120));
+        limiter.sweep(2, 2, clock.now());
+        assert!(limiter.is_empty());
+    }
+}
@@ -0,0 +1,66 @@
+//! Sampling for hot log lines that would otherwise flood the output.
+
+/// Lets through one event in every `every`, and keeps count of the rest.
+///
+/// # Examples
+///
+/// ```
+/// use utils::sampling::Sampler;
+///
+/// let mut sampler = Sampler::new(10);
+/// let logged = (0..100).filter(|_| sampler.sample().is_some()).count();
+/// assert_eq!(logged, 10);
+/// assert_eq!(sampler.skipped_total(), 90);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    every: u64,
+    seen: u64,
+    skipped_since_last: u64,
+    skipped_total: u64,
+}
+
+impl Sampler {
+    /// Creates a sampler that lets one in `every` events through. `0` is treated as `1`.
+    pub fn new(every: u64) -> Self {
+        Self {
+            every: every.max(1),
+            seen: 0,
+            skipped_since_last: 0,
+            skipped_total: 0,
+        }
+    }
+
+    /// Records an event. Returns `Some(skipped)` if this one should be logged, where `skipped`
+    /// is the number of events dropped since the last one that was, or `None` to skip it.
+    pub fn sample(&mut self) -> Option<u64> {
+        let take = self.seen.is_multiple_of(self.every);
+        self.seen += 1;
+        if take {
+            Some(std::mem::take(&mut self.skipped_since_last))
+        } else {
+            self.skipped_since_last += 1;
+            self.skipped_total += 1;
+            None
+        }
+    }
+
+    /// Total number of events skipped so far.
+    pub fn skipped_total(&self) -> u64 {
+        self.skipped_total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_sample_reports_what_was_skipped_since_the_last() {
+        let mut sampler = Sampler::new(3);
+        assert_eq!(sampler.sample(), Some(0));
+        assert_eq!(sampler.sample(), None);
+        assert_eq!(sampler.sample(), None);
+        assert_eq!(sampler.sample(), Some(2));
+    }
+}
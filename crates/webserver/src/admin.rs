@@ -0,0 +1,313 @@
+// Operator endpoints - only answered on the box itself, and only with the admin token
+use appstate::{AppState, ConnectionEvent, ConnectionId};
+use axum::extract::{ConnectInfo, Path, Query, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime};
+use tracing::*;
+
+/// Admin routes, as a [`RouteModule`](crate::RouteModule).
+///
+/// Every request has to come from loopback without forwarding headers and carry
+/// `Authorization: Bearer <admin_token>`. With no `admin_token` configured they all get a 403.
+///
+/// `POST /admin/drain` stops new WebSocket upgrades (they get a 503) while existing
+/// connections carry on. With `?exit_after_secs=N` the process also exits once the last
+/// connection is gone, or after `N` seconds, whichever comes first. Connections still open
+/// at that point are closed spread over `disconnect_jitter_ms`.
+///
+/// `GET /admin/connections/{id}/diagnostics` returns [`ConnectionDiagnostics`] as JSON, or a
+/// 404 once the connection is gone. `GET /admin/connections/closed` lists the connections that
+/// went most recently, with their last activity and the errors and close frames on the way
+/// out.
+///
+/// `GET /admin/rooms?top=N` lists the `N` busiest rooms (default 10) by member count.
+/// `POST /admin/log-filter` swaps the log filter for the directive in the body (`RUST_LOG`
+/// syntax), or back to the default with an empty body.
+///
+/// ```
+/// # let db = db::DatabaseConnection::new(std::path::Path::new(":memory:")).unwrap();
+/// # let state = appstate::AppState::new(config::Config::default(), db);
+/// let router = webserver::get_router(state, &[webserver::admin::routes]);
+/// ```
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/admin/drain", post(drain))
+        .route("/admin/connections/{id}/diagnostics", get(diagnostics))
+        .route("/admin/connections/closed", get(closed_connections))
+        .route("/admin/rooms", get(rooms))
+        .route("/admin/log-filter", post(log_filter))
+}
+
+/// A snapshot of one connection, for chasing down "it's laggy" reports.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConnectionDiagnostics {
+    pub id: u64,
+    pub username: Option<String>,
+    pub ip: Option<IpAddr>,
+    /// Unix milliseconds.
+    pub connected_at_unix_ms: u64,
+    /// When the client last sent a frame, in unix milliseconds. Its connect time if it never
+    /// has.
+    pub last_activity_unix_ms: u64,
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    /// Messages waiting to be written to the socket. Stuck near `queue_capacity` means a
+    /// slow client.
+    pub queued: usize,
+    pub queue_capacity: usize,
+    pub rooms: Vec<String>,
+    /// The connection's latest errors and closes, oldest first.
+    pub recent_events: Vec<EventReport>,
+}
+
+/// One [`ConnectionEvent`] as the admin endpoints report it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EventReport {
+    pub at_unix_ms: u64,
+    /// Set for closes, from either side.
+    pub close_code: Option<u16>,
+    pub detail: String,
+}
+
+impl From<ConnectionEvent> for EventReport {
+    fn from(event: ConnectionEvent) -> Self {
+        Self {
+            at_unix_ms: unix_ms(event.at),
+            close_code: event.close_code,
+            detail: event.detail,
+        }
+    }
+}
+
+/// Gather what the registry knows about connection `id`, or `None` if it's not connected.
+///
+/// ```
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// # let db = db::DatabaseConnection::new(std::path::Path::new(":memory:")).unwrap();
+/// # let state = appstate::AppState::new(config::Config::default(), db);
+/// # let id = appstate::ConnectionId(1);
+/// if let Some(diag) = webserver::admin::connection_diagnostics(&state, id).await {
+///     println!("{} queued of {}", diag.queued, diag.queue_capacity);
+/// }
+/// # });
+/// ```
+pub async fn connection_diagnostics(
+    state: &AppState,
+    id: ConnectionId,
+) -> Option<ConnectionDiagnostics> {
+    let registry = &state.ws_connections;
+    let sender = registry.get(id).await?;
+    let rooms = registry.rooms_of(id).await?;
+    let meta = registry.get_meta(id).await?;
+    let activity = registry.activity(id).await?;
+    let stats = sender.stats();
+    let (queued, queue_capacity) = sender.queued();
+    Some(ConnectionDiagnostics {
+        id: id.0,
+        username: meta.username,
+        ip: meta.ip,
+        connected_at_unix_ms: unix_ms(meta.connected_at),
+        last_activity_unix_ms: unix_ms(activity.last_activity()),
+        messages_sent: stats.messages(),
+        bytes_sent: stats.bytes(),
+        queued,
+        queue_capacity,
+        rooms,
+        recent_events: activity
+            .recent_events()
+            .into_iter()
+            .map(EventReport::from)
+            .collect(),
+    })
+}
+
+// A clock before 1970 isn't worth failing a diagnostics request over
+fn unix_ms(at: SystemTime) -> u64 {
+    at.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+async fn diagnostics(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(id): Path<u64>,
+) -> Response {
+    if let Err(refused) = authorize(&state, peer, &headers).await {
+        return refused;
+    }
+    match connection_diagnostics(&state, ConnectionId(id)).await {
+        Some(diag) => Json(diag).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+// Meant for turning one crate up during an investigation and back down after
+async fn log_filter(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    directive: String,
+) -> Response {
+    if let Err(refused) = authorize(&state, peer, &headers).await {
+        return refused;
+    }
+    match prettylogs::set_log_filter(&directive) {
+        Ok(()) => "log filter updated\n".into_response(),
+        Err(e @ prettylogs::LogFilterError::Invalid(_)) => {
+            (StatusCode::BAD_REQUEST, format!("{}\n", e)).into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("{}\n", e)).into_response(),
+    }
+}
+
+// The connections that went most recently, newest first, and how each one ended
+async fn closed_connections(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(refused) = authorize(&state, peer, &headers).await {
+        return refused;
+    }
+    let closed: Vec<_> = state
+        .ws_connections
+        .recent_closes()
+        .into_iter()
+        .map(|closed| {
+            let events: Vec<EventReport> =
+                closed.events.into_iter().map(EventReport::from).collect();
+            serde_json::json!({
+                "id": closed.id.0,
+                "username": closed.meta.username,
+                "ip": closed.meta.ip,
+                "connected_at_unix_ms": unix_ms(closed.meta.connected_at),
+                "last_activity_unix_ms": unix_ms(closed.last_activity),
+                "closed_at_unix_ms": unix_ms(closed.closed_at),
+                "events": events,
+            })
+        })
+        .collect();
+    Json(closed).into_response()
+}
+
+// How many rooms /admin/rooms lists when not told otherwise
+const DEFAULT_TOP_ROOMS: usize = 10;
+
+#[derive(Deserialize)]
+struct RoomsParams {
+    top: Option<usize>,
+}
+
+async fn rooms(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(params): Query<RoomsParams>,
+) -> Response {
+    if let Err(refused) = authorize(&state, peer, &headers).await {
+        return refused;
+    }
+    let top = params.top.unwrap_or(DEFAULT_TOP_ROOMS);
+    let (rooms, total) = state.ws_connections.largest_rooms(top).await;
+    let rooms: Vec<_> = rooms
+        .into_iter()
+        .map(|(name, members)| serde_json::json!({ "name": name, "members": members }))
+        .collect();
+    Json(serde_json::json!({ "total": total, "rooms": rooms })).into_response()
+}
+
+#[derive(Deserialize)]
+struct DrainParams {
+    exit_after_secs: Option<u64>,
+}
+
+async fn drain(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(params): Query<DrainParams>,
+) -> Response {
+    if let Err(refused) = authorize(&state, peer, &headers).await {
+        return refused;
+    }
+
+    let already = state.draining.swap(true, Ordering::Relaxed);
+    let open = state.ws_connections.count().await;
+    info!(
+        "Draining: refusing new connections, {} still open{}",
+        open,
+        if already { " (already draining)" } else { "" }
+    );
+
+    if let Some(secs) = params.exit_after_secs {
+        tokio::spawn(async move {
+            if !state.wait_until_drained(Duration::from_secs(secs)).await {
+                warn!(
+                    "Drain timed out after {}s with {} connections left, closing them",
+                    secs,
+                    state.ws_connections.count().await
+                );
+                // Spread out, or the stragglers all land on the next instance at once
+                state.disconnect_all("server draining").await;
+            }
+            state.request_exit();
+        });
+    }
+
+    format!("draining, {} connections open\n", open).into_response()
+}
+
+// Checked by every admin handler. Loopback alone isn't enough: a reverse proxy on the same
+// host that doesn't add forwarding headers would let anyone through, so the token is required
+async fn authorize(
+    state: &AppState,
+    peer: SocketAddr,
+    headers: &HeaderMap,
+) -> Result<(), Response> {
+    if !is_local(peer, headers) {
+        return Err(StatusCode::FORBIDDEN.into_response());
+    }
+    let Some(token) = state.config.lock().await.admin_token.clone() else {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "admin endpoints are disabled until admin_token is set\n",
+        )
+            .into_response());
+    };
+    let presented = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match presented {
+        Some(presented) if tokens_match(presented, &token) => Ok(()),
+        _ => Err((
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+        )
+            .into_response()),
+    }
+}
+
+// Compares every byte so the response time doesn't give away how much of a guess was right
+fn tokens_match(presented: &str, expected: &str) -> bool {
+    presented.len() == expected.len()
+        && presented
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// The peer has to be loopback, and not a proxy forwarding someone else's request
+fn is_local(peer: SocketAddr, headers: &HeaderMap) -> bool {
+    peer.ip().is_loopback()
+        && !headers.contains_key("forwarded")
+        && !headers.contains_key("x-forwarded-for")
+}
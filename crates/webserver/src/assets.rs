@@ -0,0 +1,189 @@
+// Static files baked into the binary, served with whatever content type they need
+// With static_dir set, a file of the same name on disk wins - edit and refresh, no rebuild
+use appstate::AppState;
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::{HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use tracing::*;
+
+// Embedded assets rarely change between releases, a day of caching is plenty
+const ASSET_CACHE_CONTROL: &str = "public, max-age=86400";
+
+// Every file we serve: (path relative to the site root, contents, cacheable)
+// Pages and scripts aren't cached so a new release shows up on the next load.
+// Add a line here instead of writing a new handler
+const EMBEDDED: &[(&str, &[u8], bool)] = &[
+    ("index.html", include_bytes!("htmlsrc/index.html"), false),
+    ("index.js", include_bytes!("htmlsrc/index.js"), false),
+    (
+        "jquery.min.js",
+        include_bytes!("htmlsrc/jquery.min.js"),
+        false,
+    ),
+    (
+        "stylesheet.css",
+        include_bytes!("htmlsrc/stylesheet.css"),
+        false,
+    ),
+    ("favicon.ico", include_bytes!("htmlsrc/favicon.ico"), true),
+    ("assets/logo.svg", include_bytes!("htmlsrc/logo.svg"), true),
+];
+
+/// A response with an arbitrary content type, for assets axum_extra has no wrapper for.
+///
+/// ```
+/// use axum::http::header;
+/// use axum::response::IntoResponse;
+/// use webserver::assets::TypedAsset;
+///
+/// let logo = webserver::assets::embedded("assets/logo.svg").unwrap();
+/// let response = logo.into_response();
+/// assert_eq!(response.headers()[header::CONTENT_TYPE], "image/svg+xml");
+/// assert_eq!(response.headers()[header::CACHE_CONTROL], "public, max-age=86400");
+///
+/// let wasm = TypedAsset::new("application/wasm", vec![0, 97, 115, 109]).no_cache();
+/// let response = wasm.into_response();
+/// assert_eq!(response.headers()[header::CONTENT_TYPE], "application/wasm");
+/// assert!(response.headers().get(header::CACHE_CONTROL).is_none());
+/// ```
+#[derive(Debug, Clone)]
+pub struct TypedAsset {
+    content_type: &'static str,
+    cache_control: Option<&'static str>,
+    body: Bytes,
+}
+
+impl TypedAsset {
+    pub fn new(content_type: &'static str, body: impl Into<Bytes>) -> Self {
+        Self {
+            content_type,
+            cache_control: Some(ASSET_CACHE_CONTROL),
+            body: body.into(),
+        }
+    }
+
+    // For anything generated per request
+    pub fn no_cache(mut self) -> Self {
+        self.cache_control = None;
+        self
+    }
+}
+
+impl IntoResponse for TypedAsset {
+    fn into_response(self) -> Response {
+        let mut response = self.body.into_response();
+        let headers = response.headers_mut();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(self.content_type),
+        );
+        if let Some(cache_control) = self.cache_control {
+            headers.insert(
+                header::CACHE_CONTROL,
+                HeaderValue::from_static(cache_control),
+            );
+        }
+        response
+    }
+}
+
+// Look up a file from the embedded table by name
+pub fn embedded(name: &str) -> Option<TypedAsset> {
+    let (file, body, cached) = EMBEDDED.iter().find(|(file, ..)| *file == name)?;
+    let asset = TypedAsset::new(content_type_for(file), Bytes::from_static(body));
+    Some(if *cached { asset } else { asset.no_cache() })
+}
+
+/// The asset at `name`, from `static_dir` if it's configured and has the file, else embedded.
+///
+/// Only names in the embedded table are served, so nothing else in `static_dir` is reachable.
+/// Files from disk are never cached, since the point is to edit them.
+///
+/// ```
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// # let db = db::DatabaseConnection::new(std::path::Path::new(":memory:")).unwrap();
+/// # let state = appstate::AppState::new(config::Config::default(), db);
+/// let stylesheet = webserver::assets::resolve(&state, "stylesheet.css").await;
+/// assert!(stylesheet.is_some());
+/// assert!(webserver::assets::resolve(&state, "../secret").await.is_none());
+/// # });
+/// ```
+pub async fn resolve(state: &AppState, name: &str) -> Option<TypedAsset> {
+    let embedded = embedded(name)?;
+    let Some(dir) = state.config.lock().await.static_dir.clone() else {
+        return Some(embedded);
+    };
+
+    let path = std::path::Path::new(&dir).join(name);
+    match tokio::fs::read(&path).await {
+        Ok(body) => Some(TypedAsset::new(content_type_for(name), body).no_cache()),
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!(
+                    "Can't read {}, serving the embedded copy: {}",
+                    path.display(),
+                    e
+                );
+            }
+            Some(embedded)
+        }
+    }
+}
+
+// Good enough for what we embed - unknown extensions are served as raw bytes
+pub fn content_type_for(name: &str) -> &'static str {
+    match name.rsplit_once('.').map(|(_, ext)| ext) {
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("ico") => "image/x-icon",
+        Some("json") => "application/json",
+        Some("wasm") => "application/wasm",
+        // Same as axum's Html and axum_extra's JavaScript/Css wrappers
+        Some("js") => "application/javascript; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("html") => "text/html; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/",
+            get(|state: State<AppState>| serve(state, "index.html")),
+        )
+        .route(
+            "/index.js",
+            get(|state: State<AppState>| serve(state, "index.js")),
+        )
+        .route(
+            "/jquery.min.js",
+            get(|state: State<AppState>| serve(state, "jquery.min.js")),
+        )
+        .route(
+            "/stylesheet.css",
+            get(|state: State<AppState>| serve(state, "stylesheet.css")),
+        )
+        .route(
+            "/favicon.ico",
+            get(|state: State<AppState>| serve(state, "favicon.ico")),
+        )
+        .route("/assets/{name}", get(get_asset))
+}
+
+async fn serve(State(state): State<AppState>, name: &'static str) -> Response {
+    match resolve(&state, name).await {
+        Some(asset) => asset.into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn get_asset(State(state): State<AppState>, Path(name): Path<String>) -> Response {
+    match resolve(&state, &format!("assets/{}", name)).await {
+        Some(asset) => asset.into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
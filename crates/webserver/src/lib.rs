@@ -1,71 +1,164 @@
 #![allow(unused_imports)]
-use appstate::{AppState, ConnectionId, MessageSender};
+pub mod admin;
+pub mod assets;
+pub mod proxy;
+
+use appstate::{
+    AppState, CLOSE_GOING_AWAY, CLOSE_POLICY_VIOLATION, Classify, CloseMessage, ConnectionActivity,
+    ConnectionEvent, ConnectionId, ConnectionSlot, DeliveryError, FrameKind, Metrics,
+    OutboundFrame, OutboundReceiver, OutboundStats, SlotError, outbound_channel,
+};
 use axum::Router;
+use config::{OutboundQuota, QuotaAction};
 
-use axum::extract::Path;
 use axum::extract::ws::{Message, WebSocketUpgrade};
-use axum::response::Html;
+use axum::extract::{ConnectInfo, Path};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{Html, IntoResponse};
 use axum::routing::{get, post};
 use axum_extra::response::*;
 use futures::{Future, SinkExt, StreamExt};
 
 use axum::body::Bytes;
-use std::time::{Duration, Instant};
+use axum_server::tls_rustls::RustlsConfig;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::net::TcpListener;
-use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
 use tokio::time::interval;
+use tower_http::compression::CompressionLayer;
+use tracing::Instrument;
 use tracing::*;
+use utils::sampling::Sampler;
+
+// How long the send task gets to flush its queue once a connection is torn down
+const SEND_FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Feature crates hand over their endpoints as one of these instead of the
+// webserver depending on them - leave a module out of the list to switch it off
+pub type RouteModule = fn() -> Router<AppState>;
 
 pub async fn start_webserver(state: AppState) {
-    start_listening(state).await;
+    start_webserver_with(state, &[admin::routes]).await;
 }
 
-fn get_router(state: AppState) -> axum::Router {
-    Router::new()
-        .route("/", get(|| async { get_index() }))
-        .route("/index.js", get(|| async { get_index_js() }))
-        .route("/jquery.min.js", get(|| async { get_jquery() }))
-        .route("/stylesheet.css", get(|| async { get_stylesheet() }))
-        .route(
-            "/ws",
-            get(
-                |ws: WebSocketUpgrade, state: axum::extract::State<AppState>| {
-                    handle_ws_upgrade(ws, state)
-                },
-            ),
-        )
+pub async fn start_webserver_with(state: AppState, modules: &[RouteModule]) {
+    start_listening(state, modules).await;
+}
+
+/// Builds the full router: the built-in pages and `/ws`, plus every route module merged in.
+///
+/// Merging panics if two modules claim the same path, same as [`Router::merge`]. HTTP
+/// responses are compressed for clients that send `Accept-Encoding`; `/ws` is left alone.
+///
+/// ```
+/// use appstate::AppState;
+/// use axum::{Router, routing::get};
+///
+/// fn export_routes() -> Router<AppState> {
+///     Router::new().route("/export", get(|| async { "exported" }))
+/// }
+///
+/// # let db = db::DatabaseConnection::new(std::path::Path::new(":memory:")).unwrap();
+/// # let state = AppState::new(config::Config::default(), db);
+/// let router = webserver::get_router(state, &[export_routes]);
+/// ```
+pub fn get_router(state: AppState, modules: &[RouteModule]) -> axum::Router {
+    modules
+        .iter()
+        .fold(core_routes(), |router, routes| router.merge(routes()))
+        // Layers only wrap routes added before them, which keeps the upgrade out of it
+        .layer(CompressionLayer::new())
+        .merge(ws_routes())
         .with_state(state)
 }
 
-async fn start_listening(state: AppState) {
-    let router = get_router(state.clone());
+// Everything the webserver serves on its own
+fn core_routes() -> Router<AppState> {
+    Router::new()
+        .route("/manifest.webmanifest", get(get_manifest))
+        .route("/metrics", get(get_metrics))
+        .route("/healthz", get(get_health))
+        .merge(assets::routes())
+}
+
+// The WebSocket upgrade, kept apart so HTTP-only middleware doesn't touch it
+fn ws_routes() -> Router<AppState> {
+    Router::new().route(
+        "/ws",
+        get(
+            |ws: WebSocketUpgrade,
+             state: axum::extract::State<AppState>,
+             ConnectInfo(peer): ConnectInfo<SocketAddr>,
+             headers: HeaderMap| { handle_ws_upgrade(ws, state, peer, headers) },
+        ),
+    )
+}
+
+async fn start_listening(state: AppState, modules: &[RouteModule]) {
+    let router = get_router(state.clone(), modules);
+    let tls = state.config.lock().await.tls.clone();
     let (internal, external) = parse_config(state).await;
     info!("Starting webserver on {} ({})", &external, &internal);
-    let listener = TcpListener::bind(&internal)
-        .await
-        .expect("Failed to bind to address");
-    let server = axum::serve(listener, router).await;
+    // Peer addresses are needed for the per-IP limits
+    let app = router.into_make_service_with_connect_info::<SocketAddr>();
+    let server = match tls {
+        None => {
+            let listener = TcpListener::bind(&internal)
+                .await
+                .expect("Failed to bind to address");
+            axum::serve(listener, app).await
+        }
+        Some(tls) => {
+            let rustls = match RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path).await {
+                Ok(rustls) => rustls,
+                Err(e) => {
+                    error!(
+                        "Failed to load TLS certificate '{}' / key '{}': \n\t{}",
+                        tls.cert_path, tls.key_path, e
+                    );
+                    return;
+                }
+            };
+            // axum-server wants an address, not a host name like "localhost"
+            let addr = tokio::net::lookup_host(&internal)
+                .await
+                .ok()
+                .and_then(|mut addrs| addrs.next())
+                .expect("Failed to resolve address");
+            axum_server::bind_rustls(addr, rustls).serve(app).await
+        }
+    };
     if let Err(e) = server {
         error!("Failed to start web server: \n\t{}", e);
     }
 }
-
-//returns the functional and display strings for the network and interface
 async fn parse_config(state: AppState) -> (String, String) {
     let config = state.config.lock().await;
     let network = config.network.clone();
     let interface = network.interface.clone();
     let port = network.port;
+    let scheme = if config.tls.is_some() {
+        "https"
+    } else {
+        "http"
+    };
     drop(network);
     drop(config);
-    let functional = format!("{}:{}", interface, port);
+    // "*" is the config's spelling of every address, which the socket wants as 0.0.0.0
+    let bind_interface = if interface == "*" {
+        "0.0.0.0"
+    } else {
+        &interface
+    };
+    let functional = format!("{}:{}", bind_interface, port);
     let display_interface: String = match interface.as_str() {
         "0.0.0.0" => "*".to_string(),
         "127.0.0.1" => "localhost".to_string(),
         _ => interface.clone(),
     };
-    let display: String = format!("{}:{}", display_interface, port);
+    let display: String = format!("{}://{}:{}", scheme, display_interface, port);
     (functional, display)
 }
 
@@ -74,21 +167,101 @@ async fn parse_config(state: AppState) -> (String, String) {
 async fn handle_ws_upgrade(
     ws: WebSocketUpgrade,
     state: axum::extract::State<AppState>,
+    peer: SocketAddr,
+    headers: HeaderMap,
 ) -> axum::response::Response {
     let state = state.0.clone();
-    ws.on_upgrade(move |socket| async move {
-        // Handle client in this async block, which will be spawned by axum
-        handle_client(socket, state.clone()).await;
-    })
+
+    // Out of rotation - send them back to the load balancer for another instance
+    if state.is_draining() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, "1")],
+            "Server is draining, reconnect to reach another instance",
+        )
+            .into_response();
+    }
+
+    // Behind a trusted proxy the peer is the proxy, the client is in the headers
+    let trusted_proxies = state.config.lock().await.network.trusted_proxies.clone();
+    let client = proxy::client_ip(peer.ip(), &headers, &trusted_proxies);
+
+    // Grab a slot before upgrading so over-limit clients get a real HTTP status
+    let slot = match reserve_slot(&state, client).await {
+        Ok(slot) => slot,
+        Err(response) => return response,
+    };
+
+    // tungstenite refuses anything bigger as soon as it reads the frame header
+    let max_message_bytes = state.config.lock().await.limits.max_message_bytes;
+    ws.max_message_size(max_message_bytes)
+        .max_frame_size(max_message_bytes)
+        .on_upgrade(move |socket| async move {
+            // Handle client in this async block, which will be spawned by axum
+            handle_client(socket, state.clone(), slot).await;
+        })
+}
+
+// Check the connection limits for this peer and hold a place for it if there's room
+async fn reserve_slot(
+    state: &AppState,
+    ip: IpAddr,
+) -> Result<ConnectionSlot, axum::response::Response> {
+    let limits = state.config.lock().await.limits.clone();
+    let allowlisted = limits.per_ip_allowlist.contains(&ip);
+    let max_per_ip = if allowlisted {
+        None
+    } else {
+        limits.max_connections_per_ip
+    };
+
+    if let Some(rate) = limits.connect_rate
+        && !allowlisted
+        && !state
+            .connect_limiter
+            .lock()
+            .expect("Connect limiter poisoned")
+            .check(ip, rate.burst, rate.per_minute, state.clock.now())
+    {
+        warn!("Rejecting WebSocket upgrade: {} is connecting too fast", ip);
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            format!("{} is opening connections too fast", ip),
+        )
+            .into_response());
+    }
+
+    state
+        .ws_connections
+        .reserve(Some(ip), limits.max_connections, max_per_ip)
+        .map_err(|e| {
+            warn!("Rejecting WebSocket upgrade: {}", e);
+            match e {
+                // Not this client's fault - another instance may have room
+                SlotError::Full { .. } => (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    [(header::RETRY_AFTER, "1")],
+                    e.to_string(),
+                )
+                    .into_response(),
+                SlotError::TooManyFromIp { .. } => {
+                    (StatusCode::TOO_MANY_REQUESTS, e.to_string()).into_response()
+                }
+            }
+        })
 }
 
 // Main entry point for WebSockets - this gets called for each connection
-// TODO: Add metrics tracking here later?
-async fn handle_client(socket: axum::extract::ws::WebSocket, state: AppState) {
+async fn handle_client(
+    socket: axum::extract::ws::WebSocket,
+    state: AppState,
+    slot: ConnectionSlot,
+) {
     debug!("New WebSocket connection established");
+    state.metrics.connection_opened();
 
     // Set up the connection and register it with the app state
-    let connection_id = setup_connection(socket, state.clone()).await;
+    let connection_id = setup_connection(socket, state.clone(), slot).await;
 
     // Once the connection is terminated, clean it up
     state.ws_connections.unregister(connection_id).await;
@@ -97,20 +270,32 @@ async fn handle_client(socket: axum::extract::ws::WebSocket, state: AppState) {
 
 // Split the connection into the parts we need and set everything up
 // This was tricky to get right - don't mess with the order of operations
-async fn setup_connection(socket: axum::extract::ws::WebSocket, state: AppState) -> ConnectionId {
+async fn setup_connection(
+    socket: axum::extract::ws::WebSocket,
+    state: AppState,
+    slot: ConnectionSlot,
+) -> ConnectionId {
     // Split the socket into sender and receiver
     let (sender, receiver) = socket.split();
 
     // Set up the message plumbing and get this connection registered
-    let (connection_id, rx) = register_connection(state.clone()).await;
+    let (connection_id, rx, stats) = register_connection(state.clone(), slot).await;
     info!("Registered new WebSocket connection: {}", connection_id);
 
     // Spin up the worker tasks - each one does a specific job
-    let tasks = spawn_connection_tasks(sender, receiver, rx, state, connection_id);
+    let quota = state.config.lock().await.limits.outbound_quota.clone();
+    let outbound = Outbound {
+        rx,
+        stats,
+        quota,
+        metrics: state.metrics.clone(),
+        activity: state.ws_connections.activity(connection_id).await,
+    };
+    let tasks = spawn_connection_tasks(sender, receiver, outbound, state.clone(), connection_id);
 
     // Wait until something breaks, then clean everything up
     // Could add reconnect logic here later if needed
-    wait_for_tasks_completion(tasks).await;
+    wait_for_tasks_completion(tasks, &state, connection_id).await;
 
     // Return the connection ID for cleanup
     connection_id
@@ -118,15 +303,40 @@ async fn setup_connection(socket: axum::extract::ws::WebSocket, state: AppState)
 
 // Create a channel and register the connection with our global state
 // IMPORTANT: This is how clients get their unique IDs
-async fn register_connection(state: AppState) -> (ConnectionId, mpsc::Receiver<Message>) {
+async fn register_connection(
+    state: AppState,
+    slot: ConnectionSlot,
+) -> (
+    ConnectionId,
+    OutboundReceiver<OutboundFrame>,
+    Arc<OutboundStats>,
+) {
     // Channel for sending messages from various tasks to the WebSocket
-    let (tx, rx) = mpsc::channel::<Message>(100);
+    // Split in two when critical frames should overtake queued pings and cursor moves
+    let prioritize = state.config.lock().await.prioritize_critical_messages;
+    let (message_sender, rx) = outbound_channel::<OutboundFrame>(100, prioritize);
+
+    // Queued before anyone else can reach the sender, so it's always the first frame
+    let _ = message_sender.send_text(state.hello().to_string()).await;
 
-    // Make a sender and register it - this lets other parts of the app message this client
-    let message_sender = MessageSender::new(tx);
-    let connection_id = state.ws_connections.register(message_sender).await;
+    // Register the sender - this lets other parts of the app message this client
+    let stats = message_sender.stats();
+    let connection_id = state
+        .ws_connections
+        .register_reserved(slot, message_sender)
+        .await;
 
-    (connection_id, rx)
+    (connection_id, rx, stats)
+}
+
+// What the send task needs: the queue to drain, where to tally, and when to cut the client off
+struct Outbound {
+    rx: OutboundReceiver<OutboundFrame>,
+    stats: Arc<OutboundStats>,
+    quota: Option<OutboundQuota>,
+    metrics: Arc<Metrics>,
+    // Where send errors are noted for the diagnostics endpoint. None if closed before we got it
+    activity: Option<Arc<ConnectionActivity>>,
 }
 
 // Fire up the three tasks we need for each connection
@@ -134,7 +344,7 @@ async fn register_connection(state: AppState) -> (ConnectionId, mpsc::Receiver<M
 fn spawn_connection_tasks(
     sender: futures::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
     receiver: futures::stream::SplitStream<axum::extract::ws::WebSocket>,
-    rx: mpsc::Receiver<Message>,
+    outbound: Outbound,
     state: AppState,
     conn_id: ConnectionId,
 ) -> (
@@ -142,7 +352,7 @@ fn spawn_connection_tasks(
     tokio::task::JoinHandle<()>,
     tokio::task::JoinHandle<()>,
 ) {
-    let send_task = spawn_send_task(sender, rx, conn_id);
+    let send_task = spawn_send_task(sender, outbound, conn_id);
     let heartbeat_task = spawn_heartbeat_task(state.clone(), conn_id);
     let receive_task = spawn_receive_task(receiver, state, conn_id);
 
@@ -157,6 +367,8 @@ async fn wait_for_tasks_completion(
         tokio::task::JoinHandle<()>,
         tokio::task::JoinHandle<()>,
     ),
+    state: &AppState,
+    conn_id: ConnectionId,
 ) {
     tokio::select! {
         _ = &mut send_task => {},
@@ -164,6 +376,20 @@ async fn wait_for_tasks_completion(
         _ = &mut receive_task => {},
     }
 
+    // Drop the registry's sender so the send task can drain what's queued (like a close frame)
+    // and finish on its own. Bounded so a stuck socket can't hold the connection open forever
+    state.ws_connections.unregister(conn_id).await;
+    if !send_task.is_finished()
+        && tokio::time::timeout(SEND_FLUSH_TIMEOUT, &mut send_task)
+            .await
+            .is_err()
+    {
+        debug!(
+            "Connection {}: Send task didn't flush in time, aborting",
+            conn_id
+        );
+    }
+
     // Abort all tasks when one completes/fails
     send_task.abort();
     heartbeat_task.abort();
@@ -174,11 +400,11 @@ async fn wait_for_tasks_completion(
 // Pretty straightforward - just a loop that pulls from channel & sends to socket
 fn spawn_send_task(
     sender: futures::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
-    rx: mpsc::Receiver<Message>,
+    outbound: Outbound,
     conn_id: ConnectionId,
 ) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async move {
-        process_outgoing_messages(sender, rx, conn_id).await;
+        process_outgoing_messages(sender, outbound, conn_id).await;
     })
 }
 
@@ -203,25 +429,97 @@ fn spawn_receive_task(
 /// Process outgoing messages from the channel to the WebSocket
 async fn process_outgoing_messages(
     mut sender: futures::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
-    mut rx: mpsc::Receiver<Message>,
+    Outbound {
+        mut rx,
+        stats,
+        quota,
+        metrics,
+        activity,
+    }: Outbound,
     conn_id: ConnectionId,
 ) {
-    while let Some(message) = rx.recv().await {
-        if let Err(e) = sender.send(message).await {
+    let mut over_quota = false;
+
+    while let Some(frame) = rx.recv().await {
+        // Once over quota in drop mode, only control frames still go out
+        if over_quota && frame.is_data() {
+            continue;
+        }
+
+        let is_close = frame.is_close();
+        let len = frame.len();
+        if let Err(e) = sender.send(Message::from(frame)).await {
             error!(
                 "Connection {}: Error sending WebSocket message: {}",
                 conn_id, e
             );
+            if let Some(activity) = &activity {
+                activity.record(ConnectionEvent {
+                    at: SystemTime::now(),
+                    close_code: None,
+                    detail: format!("send failed: {}", e),
+                });
+            }
             break;
         }
+        stats.record(len);
+        metrics.frame_sent(len);
+        // Nothing may follow a close frame on the wire
+        if is_close {
+            break;
+        }
+
+        if let Some(quota) = &quota
+            && !over_quota
+            && quota.is_exceeded(stats.messages(), stats.bytes())
+        {
+            warn!(
+                "Connection {}: Outbound quota exceeded ({} messages, {} bytes)",
+                conn_id,
+                stats.messages(),
+                stats.bytes()
+            );
+            match quota.action {
+                QuotaAction::Disconnect => {
+                    let close = OutboundFrame::create_close_message(
+                        CLOSE_POLICY_VIOLATION,
+                        "outbound quota exceeded".to_string(),
+                    );
+                    let _ = sender.send(Message::from(close)).await;
+                    break;
+                }
+                QuotaAction::Drop => over_quota = true,
+            }
+        }
     }
     debug!("Send task for connection {} terminated", conn_id);
 }
 
+/// Encodes `now` as big-endian unix milliseconds, the payload every heartbeat ping carries.
+///
+/// Clients echo it back in the pong, so they can both read the server clock and time the
+/// round trip from a single frame.
+///
+/// ```
+/// use std::time::{SystemTime, UNIX_EPOCH};
+///
+/// let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+/// let payload = webserver::server_time_payload(SystemTime::now());
+/// let unix_millis = u64::from_be_bytes(payload[..].try_into().unwrap());
+/// assert!(unix_millis >= before && unix_millis - before < 1_000);
+/// ```
+pub fn server_time_payload(now: SystemTime) -> Bytes {
+    // A clock before 1970 isn't worth failing a heartbeat over
+    let unix_millis = now
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64);
+    Bytes::copy_from_slice(&unix_millis.to_be_bytes())
+}
+
 // Keep the connection alive with pings
-// 30 sec interval seems to work well with most clients & proxies
 async fn send_heartbeats(state: AppState, conn_id: ConnectionId) {
-    let mut interval = interval(Duration::from_secs(30));
+    let every = state.config.lock().await.heartbeat.ping_interval_secs;
+    let mut interval = interval(Duration::from_secs(every));
 
     loop {
         interval.tick().await;
@@ -233,11 +531,16 @@ async fn send_heartbeats(state: AppState, conn_id: ConnectionId) {
         }
 
         // Only ping if client still exists (avoid zombies)
-        if let Some(sender) = state.ws_connections.get(conn_id).await {
-            if sender.send(Message::Ping(Bytes::new())).await.is_err() {
-                break;
-            }
-        } else {
+        // Pings are ephemeral - a backed up queue just skips this one
+        let Some(sender) = state.ws_connections.get(conn_id).await else {
+            break;
+        };
+        // Payload carries the server clock so clients can track their offset
+        let ping = OutboundFrame::Ping(server_time_payload(state.clock.system_time()));
+        let delivery = ping.delivery();
+        if sender.send_with(ping, delivery, Duration::ZERO).await
+            == Err(DeliveryError::Disconnected)
+        {
             break;
         }
     }
@@ -245,83 +548,265 @@ async fn send_heartbeats(state: AppState, conn_id: ConnectionId) {
     debug!("Heartbeat task for connection {} terminated", conn_id);
 }
 
+/// Waits for the next item, but only until `timeout` has passed since `last_seen`.
+///
+/// `Ok(None)` means the stream ended; `Err` means the client went quiet for too long.
+///
+/// ```
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// use std::time::{Duration, Instant};
+///
+/// let mut frames = futures::stream::iter([1, 2]);
+/// let result =
+///     webserver::next_before_deadline(&mut frames, Instant::now(), Duration::from_secs(30)).await;
+/// assert_eq!(result, Ok(Some(1)));
+/// # });
+/// ```
+pub async fn next_before_deadline<S>(
+    receiver: &mut S,
+    last_seen: Instant,
+    timeout: Duration,
+) -> Result<Option<S::Item>, tokio::time::error::Elapsed>
+where
+    S: futures::Stream + Unpin,
+{
+    let remaining = timeout.saturating_sub(last_seen.elapsed());
+    tokio::time::timeout(remaining, receiver.next()).await
+}
+
 // Process stuff coming from the client
 // Just basic handling for now - actual message processing happens elsewhere
+// A message over limits.max_message_bytes ends the connection with a policy violation
 async fn process_incoming_messages(
     mut receiver: futures::stream::SplitStream<axum::extract::ws::WebSocket>,
     state: AppState,
     conn_id: ConnectionId,
 ) {
     let mut last_pong = Instant::now();
-    let timeout = Duration::from_secs(90); // 3x the ping interval seems to work well
-
-    while let Some(result) = receiver.next().await {
-        match result {
-            Ok(Message::Text(text)) => {
-                // No message handling here - that's for the application layer
-                trace!(
-                    "Connection {}: Received text message of length {}",
-                    conn_id,
-                    text.len()
-                );
-            }
-            Ok(Message::Binary(data)) => {
-                // Binary messages just get logged - actual handling elsewhere
-                trace!(
-                    "Connection {}: Received binary data of size: {} bytes: \n\t{:02X?}",
-                    conn_id,
-                    data.len(),
-                    &data
-                );
-                // --- Type detection debug ---
-                // Use the descriptor set embedded at compile time
-                // --- End type detection debug ---
-            }
-            Ok(Message::Close(_)) => {
-                debug!("Connection {}: Client initiated close", conn_id);
+    let (timeout, logging, max_message_bytes) = {
+        let config = state.config.lock().await;
+        let timeout = Duration::from_secs(config.heartbeat.timeout_secs);
+        (
+            timeout,
+            config.logging.clone(),
+            config.limits.max_message_bytes,
+        )
+    };
+    let mut binary_trace = Sampler::new(logging.trace_sample_every);
+    // Short instance prefix keeps op ids unique across restarts and replicas
+    let instance = &state.instance_id[..8];
+    let mut frames_seen: u64 = 0;
+    let activity = state.ws_connections.activity(conn_id).await;
+
+    loop {
+        // A client that has gone completely silent never sends a frame that would let us
+        // notice, so the wait itself has to give up when the deadman switch runs out
+        let result = match next_before_deadline(&mut receiver, last_pong, timeout).await {
+            Ok(Some(result)) => result,
+            Ok(None) => break,
+            Err(_) => {
+                debug!("Connection {}: Client timed out", conn_id);
+                state
+                    .ws_connections
+                    .close(conn_id, CLOSE_GOING_AWAY, "heartbeat timeout")
+                    .await;
                 break;
             }
-            Ok(Message::Ping(data)) => {
-                // Gotta respond to pings - WS protocol requirement
-                if let Some(sender) = state.ws_connections.get(conn_id).await {
-                    if sender.send(Message::Pong(data)).await.is_err() {
-                        break;
+        };
+        // Everything this frame leads to logs under one op id, so an operator can grep a
+        // single operation from receive through handling and fan-out
+        frames_seen += 1;
+        if let Some(activity) = &activity {
+            activity.touch(state.clock.system_time());
+        }
+        if let Ok(message) = &result {
+            let (kind, len) = match message {
+                Message::Text(text) => (FrameKind::Text, text.len()),
+                Message::Binary(data) => (FrameKind::Binary, data.len()),
+                Message::Ping(data) => (FrameKind::Ping, data.len()),
+                Message::Pong(data) => (FrameKind::Pong, data.len()),
+                Message::Close(_) => (FrameKind::Close, 0),
+            };
+            state.metrics.frame_received(kind, len);
+        }
+        // The upgrade already caps this, but don't rely on that alone
+        let oversized = match &result {
+            Ok(Message::Text(text)) => text.len() > max_message_bytes,
+            Ok(Message::Binary(data)) => data.len() > max_message_bytes,
+            Ok(_) => false,
+            Err(e) => is_capacity_error(e),
+        };
+        if oversized {
+            warn!(
+                "Connection {}: Message over the {} byte limit, disconnecting",
+                conn_id, max_message_bytes
+            );
+            state
+                .ws_connections
+                .close(conn_id, CLOSE_POLICY_VIOLATION, "message too big")
+                .await;
+            break;
+        }
+        let span =
+            debug_span!("frame", op = %format_args!("{}-{}.{}", instance, conn_id, frames_seen));
+        let keep_going = async {
+            match result {
+                Ok(Message::Text(text)) => {
+                    // No message handling here - that's for the application layer
+                    trace!(
+                        "Connection {}: Received text message of length {}",
+                        conn_id,
+                        text.len()
+                    );
+                }
+                Ok(Message::Binary(data)) => {
+                    // Binary messages just get logged - actual handling elsewhere
+                    // Sampled and truncated since this is the hottest log line we have
+                    if tracing::enabled!(Level::TRACE)
+                        && let Some(skipped) = binary_trace.sample()
+                    {
+                        let shown = data.len().min(logging.trace_max_bytes);
+                        trace!(
+                            "Connection {}: Received binary data of size: {} bytes ({} frames skipped): \n\t{:02X?}{}",
+                            conn_id,
+                            data.len(),
+                            skipped,
+                            &data[..shown],
+                            if shown < data.len() { " ..." } else { "" }
+                        );
                     }
+                    // --- Type detection debug ---
+                    // Use the descriptor set embedded at compile time
+                    // --- End type detection debug ---
+                }
+                Ok(Message::Close(frame)) => {
+                    debug!("Connection {}: Client initiated close", conn_id);
+                    if let Some(activity) = &activity {
+                        // 1005: the client's close frame didn't carry a code
+                        let (code, reason) = match frame {
+                            Some(frame) => (frame.code, frame.reason),
+                            None => (1005, Default::default()),
+                        };
+                        activity.record(ConnectionEvent {
+                            at: state.clock.system_time(),
+                            close_code: Some(code),
+                            detail: format!("client closed: {}", reason),
+                        });
+                    }
+                    return false;
+                }
+                Ok(Message::Ping(data)) => {
+                    // Gotta respond to pings - WS protocol requirement
+                    if let Some(sender) = state.ws_connections.get(conn_id).await
+                        && sender.send(OutboundFrame::Pong(data)).await.is_err()
+                    {
+                        return false;
+                    }
+                }
+                Ok(Message::Pong(_)) => {
+                    // Client is still alive, reset the deadman switch
+                    last_pong = Instant::now();
+                    // Silently update timestamp, no logging needed
+                }
+                Err(e) => {
+                    debug!("Connection {}: WebSocket error: {}", conn_id, e);
+                    if let Some(activity) = &activity {
+                        activity.record(ConnectionEvent {
+                            at: state.clock.system_time(),
+                            close_code: None,
+                            detail: format!("receive failed: {}", e),
+                        });
+                    }
+                    return false;
                 }
             }
-            Ok(Message::Pong(_)) => {
-                // Client is still alive, reset the deadman switch
-                last_pong = Instant::now();
-                // Silently update timestamp, no logging needed
-            }
-            Err(e) => {
-                debug!("Connection {}: WebSocket error: {}", conn_id, e);
-                break;
-            }
+            true
         }
-
-        // Check if client ghosted us
-        if last_pong.elapsed() > timeout {
-            debug!("Connection {}: Client timed out", conn_id);
+        .instrument(span)
+        .await;
+        if !keep_going {
             break;
         }
     }
 
+    if binary_trace.skipped_total() > 0 {
+        debug!(
+            "Connection {}: Skipped tracing {} binary frames",
+            conn_id,
+            binary_trace.skipped_total()
+        );
+    }
     debug!("Receive task for connection {} terminated", conn_id);
 }
 
-fn get_index() -> Html<String> {
-    include_str!("htmlsrc/index.html").to_string().into()
+// Longest /healthz waits on the database before calling it down
+const HEALTH_DB_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Liveness and readiness in one: 200 while the process runs and the database answers,
+// 503 otherwise. Cheap enough to poll every few seconds
+async fn get_health(state: axum::extract::State<AppState>) -> impl IntoResponse {
+    let running = state.running.load(std::sync::atomic::Ordering::Relaxed);
+    let database = tokio::time::timeout(HEALTH_DB_TIMEOUT, state.db.run(|db| db.ping())).await;
+    let database_ok = matches!(database, Ok(Ok(())));
+    if !database_ok {
+        warn!("Health check: database didn't answer");
+    }
+    let status = if running && database_ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = serde_json::json!({
+        "status": if status == StatusCode::OK { "ok" } else { "unavailable" },
+        "database": if database_ok { "ok" } else { "unavailable" },
+        "connections": state.ws_connections.count().await,
+        // Changes on every restart, for pollers that want to notice one
+        "instance_id": &*state.instance_id,
+        "boot_time_unix_ms": state.boot_time_unix_ms(),
+    });
+    (status, axum::Json(body))
 }
 
-fn get_index_js() -> JavaScript<String> {
-    include_str!("htmlsrc/index.js").to_string().into()
+// Rooms get a series each, so only the busiest are exported to keep cardinality bounded
+const METRICS_ROOM_LABELS: usize = 20;
+
+// Prometheus scrape target. Active connections come from the registry, so they're never stale
+async fn get_metrics(state: axum::extract::State<AppState>) -> impl IntoResponse {
+    let (rooms, total_rooms) = state
+        .ws_connections
+        .largest_rooms(METRICS_ROOM_LABELS)
+        .await;
+    let body = state
+        .metrics
+        .render(state.ws_connections.count().await, &rooms, total_rooms);
+    (
+        [(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        body,
+    )
 }
 
-fn get_jquery() -> JavaScript<String> {
-    include_str!("htmlsrc/jquery.min.js").to_string().into()
+// What axum hands back when a frame goes over max_message_size / max_frame_size
+fn is_capacity_error(e: &axum::Error) -> bool {
+    std::error::Error::source(e)
+        .and_then(|source| source.downcast_ref::<tungstenite::Error>())
+        .is_some_and(|e| matches!(e, tungstenite::Error::Capacity(_)))
 }
 
-fn get_stylesheet() -> Css<String> {
-    include_str!("htmlsrc/stylesheet.css").to_string().into()
+// Built from the branding config so each deployment can name and color its own app
+async fn get_manifest(state: axum::extract::State<AppState>) -> impl IntoResponse {
+    let branding = state.config.lock().await.branding.clone();
+    let manifest = serde_json::json!({
+        "name": branding.app_name,
+        "short_name": branding.short_name,
+        "start_url": "/",
+        "display": "standalone",
+        "theme_color": branding.theme_color,
+        "background_color": branding.background_color,
+        "icons": [{ "src": "/favicon.ico", "sizes": "32x32", "type": "image/x-icon" }],
+    });
+    let body = config::serialize_json(&manifest, false).expect("Manifest is always valid JSON");
+    ([(header::CONTENT_TYPE, "application/manifest+json")], body)
 }
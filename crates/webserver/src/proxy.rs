@@ -0,0 +1,82 @@
+//! Working out the real client address when we sit behind a reverse proxy.
+
+use axum::http::HeaderMap;
+use std::net::{IpAddr, SocketAddr};
+
+/// The address of the client behind `peer`.
+///
+/// Forwarding headers are only believed when `peer` is one of `trusted_proxies`, so anyone
+/// connecting directly can't spoof their address. The `Forwarded` header wins over
+/// `X-Forwarded-For`. The chain is walked from the right, skipping trusted proxies, and the
+/// first hop that isn't one of ours is the client.
+///
+/// # Example
+/// ```
+/// use axum::http::HeaderMap;
+/// use std::net::IpAddr;
+/// use webserver::proxy::client_ip;
+///
+/// let proxy: IpAddr = "10.0.0.2".parse().unwrap();
+/// let mut headers = HeaderMap::new();
+/// headers.insert("x-forwarded-for", "203.0.113.9".parse().unwrap());
+/// assert_eq!(client_ip(proxy, &headers, &[proxy]), "203.0.113.9".parse::<IpAddr>().unwrap());
+/// ```
+pub fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpAddr]) -> IpAddr {
+    if !trusted_proxies.contains(&peer) {
+        return peer;
+    }
+
+    let hops = forwarded_hops(headers);
+    let mut client = peer;
+    for hop in hops.iter().rev() {
+        match parse_hop(hop) {
+            Some(ip) if trusted_proxies.contains(&ip) => client = ip,
+            Some(ip) => return ip,
+            // Garbage or "unknown" - the last hop we could vouch for is the best we can do
+            None => break,
+        }
+    }
+    client
+}
+
+// Every forwarded-for entry in order, oldest (the original client) first
+fn forwarded_hops(headers: &HeaderMap) -> Vec<String> {
+    let forwarded: Vec<String> = headers
+        .get_all("forwarded")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim()
+                    .eq_ignore_ascii_case("for")
+                    .then(|| value.trim().to_string())
+            })
+        })
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|hop| hop.trim().to_string())
+        .collect()
+}
+
+// Accepts 1.2.3.4, 1.2.3.4:80, 2001:db8::1, [2001:db8::1]:80, optionally quoted
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim().trim_matches('"');
+    hop.parse::<IpAddr>()
+        .ok()
+        .or_else(|| hop.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            hop.strip_prefix('[')
+                .and_then(|rest| rest.split_once(']'))
+                .and_then(|(ip, _)| ip.parse().ok())
+        })
+}
@@ -0,0 +1,150 @@
+//! Shared setup for the webserver integration tests: a real server on a loopback port.
+#![allow(dead_code)] // Each test binary uses its own subset
+
+use appstate::AppState;
+use futures::StreamExt;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_tungstenite::tungstenite::Message;
+
+pub type Client =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+pub struct TestServer {
+    pub addr: SocketAddr,
+    pub state: AppState,
+}
+
+/// A server with `config`, an in-memory database and the built-in routes.
+pub async fn spawn_with(config: config::Config) -> TestServer {
+    spawn_with_modules(config, &[]).await
+}
+
+/// Like [`spawn_with`], plus the routes from `modules`.
+pub async fn spawn_with_modules(
+    config: config::Config,
+    modules: &[webserver::RouteModule],
+) -> TestServer {
+    let db = db::DatabaseConnection::new(std::path::Path::new(":memory:")).unwrap();
+    let state = AppState::new(config, db);
+    let router = webserver::get_router(state.clone(), modules);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let app = router.into_make_service_with_connect_info::<SocketAddr>();
+        axum::serve(listener, app).await
+    });
+    TestServer { addr, state }
+}
+
+pub async fn spawn() -> TestServer {
+    spawn_with(config::Config::default()).await
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body).unwrap()
+    }
+}
+
+/// Send a raw HTTP/1.1 request and read the whole response.
+pub async fn request(addr: SocketAddr, method: &str, path: &str, extra_headers: &str) -> Response {
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let head = format!(
+        "{} {} HTTP/1.1\r\nHost: test\r\nConnection: close\r\nContent-Length: 0\r\n{}\r\n",
+        method, path, extra_headers
+    );
+    stream.write_all(head.as_bytes()).await.unwrap();
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).await.unwrap();
+
+    let split = raw.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8(raw[..split].to_vec()).unwrap();
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .unwrap()
+        .split(' ')
+        .nth(1)
+        .unwrap()
+        .parse()
+        .unwrap();
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+    let mut body = raw[split + 4..].to_vec();
+    // Streamed bodies come chunked; tests only ever need the payload
+    if head
+        .to_ascii_lowercase()
+        .contains("transfer-encoding: chunked")
+    {
+        body = dechunk(&body);
+    }
+    Response {
+        status,
+        headers,
+        body,
+    }
+}
+
+pub async fn get(addr: SocketAddr, path: &str) -> Response {
+    request(addr, "GET", path, "").await
+}
+
+fn dechunk(mut raw: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    loop {
+        let line_end = raw.windows(2).position(|w| w == b"\r\n").unwrap();
+        let size_line = std::str::from_utf8(&raw[..line_end]).unwrap();
+        let size = usize::from_str_radix(size_line.split(';').next().unwrap().trim(), 16).unwrap();
+        if size == 0 {
+            return out;
+        }
+        let start = line_end + 2;
+        out.extend_from_slice(&raw[start..start + size]);
+        raw = &raw[start + size + 2..];
+    }
+}
+
+/// Open a WebSocket to the server and skip past its hello frame.
+pub async fn connect(addr: SocketAddr) -> Client {
+    let (client, hello) = connect_with_hello(addr).await;
+    assert_eq!(hello["type"], "hello");
+    client
+}
+
+/// Open a WebSocket and return the hello frame the server greets it with.
+pub async fn connect_with_hello(addr: SocketAddr) -> (Client, serde_json::Value) {
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+        .await
+        .unwrap();
+    let hello = match next_message(&mut client).await {
+        Message::Text(text) => serde_json::from_str(&text).unwrap(),
+        other => panic!("expected a hello frame, got {:?}", other),
+    };
+    (client, hello)
+}
+
+/// The next frame from the server, failing the test if none comes within a few seconds.
+pub async fn next_message(client: &mut Client) -> Message {
+    tokio::time::timeout(Duration::from_secs(5), client.next())
+        .await
+        .expect("timed out waiting for the server")
+        .expect("connection ended")
+        .expect("WebSocket error")
+}
@@ -0,0 +1,74 @@
+// Every log line caused by a frame carries that frame's op id
+mod common;
+
+use futures::SinkExt;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+
+// Collects formatted log output so the test can read it back
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Captured {
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+}
+
+#[tokio::test]
+async fn events_from_handling_a_frame_carry_its_op_id() {
+    let captured = Captured::default();
+    let writer = captured.clone();
+    // Global, since the receive task runs on whichever worker thread picks it up. This is
+    // the only test in this binary, so nothing else competes for it
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::TRACE)
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .init();
+
+    let server = common::spawn().await;
+    let mut client = common::connect(server.addr).await;
+    let (id, _) = server.state.ws_connections.list_connections().await[0].clone();
+    client.send(Message::Text("first".into())).await.unwrap();
+    client.send(Message::Text("second".into())).await.unwrap();
+
+    let instance = &server.state.instance_id[..8];
+    let expected = [
+        format!("frame{{op={}-{}.1}}", instance, id),
+        format!("frame{{op={}-{}.2}}", instance, id),
+    ];
+    let logs = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let logs = captured.text();
+            if logs.matches("Received text message").count() >= 2 {
+                return logs;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the server never logged the frames");
+
+    let handled: Vec<&str> = logs
+        .lines()
+        .filter(|line| line.contains("Received text message"))
+        .collect();
+    assert_eq!(handled.len(), 2, "{}", logs);
+    for (line, op) in handled.iter().zip(&expected) {
+        assert!(line.contains(op.as_str()), "{:?} should carry {}", line, op);
+    }
+}
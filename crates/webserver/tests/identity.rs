@@ -0,0 +1,34 @@
+// Clients have to be able to tell a restarted server from the one they just lost
+mod common;
+
+#[tokio::test]
+async fn hello_frame_names_the_instance() {
+    let server = common::spawn().await;
+    let (_client, hello) = common::connect_with_hello(server.addr).await;
+
+    assert_eq!(hello["type"], "hello");
+    assert_eq!(hello["instance_id"], &*server.state.instance_id);
+    assert_eq!(hello["boot_time_unix_ms"], server.state.boot_time_unix_ms());
+    assert!(server.state.boot_time_unix_ms() > 0);
+}
+
+#[tokio::test]
+async fn healthz_names_the_instance() {
+    let server = common::spawn().await;
+    let response = common::get(server.addr, "/healthz").await;
+
+    assert_eq!(response.status, 200);
+    let body = response.json();
+    assert_eq!(body["instance_id"], &*server.state.instance_id);
+    assert_eq!(body["boot_time_unix_ms"], server.state.boot_time_unix_ms());
+}
+
+#[tokio::test]
+async fn a_restart_shows_up_as_a_new_instance() {
+    let first = common::spawn().await;
+    let second = common::spawn().await;
+    let (_a, before) = common::connect_with_hello(first.addr).await;
+    let (_b, after) = common::connect_with_hello(second.addr).await;
+
+    assert_ne!(before["instance_id"], after["instance_id"]);
+}
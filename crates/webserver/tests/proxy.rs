@@ -0,0 +1,37 @@
+// Which address a request is credited to, with and without a proxy in front
+use axum::http::HeaderMap;
+use std::net::IpAddr;
+use webserver::proxy::client_ip;
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn forwarded_for_is_walked_from_the_right() {
+    let proxy = ip("10.0.0.2");
+    let mut headers = HeaderMap::new();
+    // The client claims to be 1.1.1.1, the proxy appended what it actually saw
+    headers.insert("x-forwarded-for", "1.1.1.1, 203.0.113.9".parse().unwrap());
+    assert_eq!(client_ip(proxy, &headers, &[proxy]), ip("203.0.113.9"));
+}
+
+#[test]
+fn headers_from_untrusted_peers_are_ignored() {
+    let proxy = ip("10.0.0.2");
+    let stranger = ip("198.51.100.4");
+    let mut headers = HeaderMap::new();
+    headers.insert("x-forwarded-for", "203.0.113.9".parse().unwrap());
+    assert_eq!(client_ip(stranger, &headers, &[proxy]), stranger);
+}
+
+#[test]
+fn forwarded_header_handles_bracketed_ipv6() {
+    let proxy = ip("10.0.0.2");
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "forwarded",
+        r#"for="[2001:db8::1]:4711";proto=https"#.parse().unwrap(),
+    );
+    assert_eq!(client_ip(proxy, &headers, &[proxy]), ip("2001:db8::1"));
+}
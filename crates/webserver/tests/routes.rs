@@ -0,0 +1,101 @@
+// The fixed HTTP routes: what they serve and with which content type
+mod common;
+
+#[tokio::test]
+async fn favicon_is_served_as_an_icon() {
+    let server = common::spawn().await;
+    let response = common::get(server.addr, "/favicon.ico").await;
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-type"), Some("image/x-icon"));
+    assert_eq!(
+        response.body,
+        include_bytes!("../src/htmlsrc/favicon.ico").as_slice()
+    );
+}
+
+#[tokio::test]
+async fn manifest_is_served_as_a_web_manifest() {
+    let mut config = config::Config::default();
+    config.branding.app_name = "Team Canvas".to_string();
+    let server = common::spawn_with(config).await;
+    let response = common::get(server.addr, "/manifest.webmanifest").await;
+
+    assert_eq!(response.status, 200);
+    assert_eq!(
+        response.header("content-type"),
+        Some("application/manifest+json")
+    );
+    let manifest = response.json();
+    assert_eq!(manifest["name"], "Team Canvas");
+    // The icon it points at is the one the favicon route serves
+    assert_eq!(manifest["icons"][0]["src"], "/favicon.ico");
+    assert_eq!(manifest["icons"][0]["type"], "image/x-icon");
+}
+
+#[tokio::test]
+async fn healthz_is_ok_with_a_working_database() {
+    let server = common::spawn().await;
+    let response = common::get(server.addr, "/healthz").await;
+
+    assert_eq!(response.status, 200);
+    let body = response.json();
+    assert_eq!(body["status"], "ok");
+    assert_eq!(body["database"], "ok");
+    assert_eq!(body["connections"], 0);
+}
+
+#[tokio::test]
+async fn route_modules_are_merged_in() {
+    fn export_routes() -> axum::Router<appstate::AppState> {
+        axum::Router::new().route("/export", axum::routing::get(|| async { "exported" }))
+    }
+    let server = common::spawn_with_modules(config::Config::default(), &[export_routes]).await;
+    let response = common::get(server.addr, "/export").await;
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, b"exported");
+}
+
+#[tokio::test]
+async fn http_responses_are_compressed_on_request() {
+    let server = common::spawn().await;
+    let response = common::request(
+        server.addr,
+        "GET",
+        "/jquery.min.js",
+        "Accept-Encoding: gzip\r\n",
+    )
+    .await;
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-encoding"), Some("gzip"));
+}
+
+#[tokio::test]
+async fn static_dir_files_win_over_embedded_ones() {
+    let dir = std::env::temp_dir().join(format!("rustcanvas-static-dir-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("index.js"), "console.log('from disk');").unwrap();
+    let config = config::Config {
+        static_dir: Some(dir.to_str().unwrap().to_string()),
+        ..config::Config::default()
+    };
+    let server = common::spawn_with(config).await;
+
+    let from_disk = common::get(server.addr, "/index.js").await;
+    assert_eq!(
+        from_disk.header("content-type"),
+        Some("application/javascript; charset=utf-8")
+    );
+    assert_eq!(from_disk.body, b"console.log('from disk');");
+    assert_eq!(from_disk.header("cache-control"), None);
+
+    // Not on disk, so the embedded copy is used
+    let embedded = common::get(server.addr, "/stylesheet.css").await;
+    assert_eq!(
+        embedded.body,
+        include_bytes!("../src/htmlsrc/stylesheet.css").as_slice()
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,57 @@
+// What the server does with the frames a client sends
+mod common;
+
+use futures::SinkExt;
+use std::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::Message;
+use webserver::next_before_deadline;
+
+#[tokio::test]
+async fn oversized_messages_close_with_a_policy_violation() {
+    let mut config = config::Config::default();
+    config.limits.max_message_bytes = 1024;
+    let server = common::spawn_with(config).await;
+    let mut client = common::connect(server.addr).await;
+
+    // Small frames are fine - the server answers our ping like always
+    client
+        .send(Message::Binary(vec![0; 512].into()))
+        .await
+        .unwrap();
+    client
+        .send(Message::Ping("still here".into()))
+        .await
+        .unwrap();
+    loop {
+        match common::next_message(&mut client).await {
+            Message::Pong(data) if data == "still here" => break,
+            Message::Close(frame) => panic!("closed early: {:?}", frame),
+            _ => {}
+        }
+    }
+
+    client
+        .send(Message::Binary(vec![0; 4096].into()))
+        .await
+        .unwrap();
+    let close = loop {
+        if let Message::Close(frame) = common::next_message(&mut client).await {
+            break frame.unwrap();
+        }
+    };
+    assert_eq!(u16::from(close.code), appstate::CLOSE_POLICY_VIOLATION);
+}
+
+#[tokio::test]
+async fn a_silent_peer_misses_the_deadline() {
+    let mut silent = futures::stream::pending::<()>();
+    let started = Instant::now();
+    let result = next_before_deadline(&mut silent, Instant::now(), Duration::from_millis(50)).await;
+    assert!(result.is_err());
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    // An ended stream is not a timeout
+    let mut ended = futures::stream::empty::<()>();
+    let result = next_before_deadline(&mut ended, Instant::now(), Duration::from_millis(50)).await;
+    assert_eq!(result, Ok(None));
+}